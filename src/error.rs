@@ -0,0 +1,124 @@
+//! ファイル処理時のエラー
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// ファイルの処理に失敗した理由を表すエラー
+#[derive(Debug)]
+pub enum ProcessError {
+    /// ファイルが存在しない
+    NotFound,
+    /// ディレクトリが指定された
+    IsDirectory,
+    /// 内容をテキストとして解釈できない
+    Encoding(io::Error),
+    /// その他の入出力エラー
+    Io(io::Error),
+    /// URLの取得に失敗した（接続できない・エラーのステータスコードが返された等）
+    Network(ureq::Error),
+    /// ZIPアーカイブとして読み込めない（壊れている・未対応の圧縮方式等）
+    Archive(zip::result::ZipError),
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::NotFound => write!(f, "no such file or directory"),
+            ProcessError::IsDirectory => write!(f, "is a directory"),
+            ProcessError::Encoding(e) => write!(f, "invalid data: {}", e),
+            ProcessError::Io(e) => write!(f, "{}", e),
+            ProcessError::Network(e) => write!(f, "could not fetch: {}", e),
+            ProcessError::Archive(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ProcessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProcessError::Encoding(e) | ProcessError::Io(e) => Some(e),
+            ProcessError::Network(e) => Some(e),
+            ProcessError::Archive(e) => Some(e),
+            ProcessError::NotFound | ProcessError::IsDirectory => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProcessError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => ProcessError::NotFound,
+            io::ErrorKind::IsADirectory => ProcessError::IsDirectory,
+            io::ErrorKind::InvalidData => ProcessError::Encoding(e),
+            _ => ProcessError::Io(e),
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for ProcessError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => ProcessError::from(e),
+            e => ProcessError::Archive(e),
+        }
+    }
+}
+
+impl From<ProcessError> for io::Error {
+    fn from(e: ProcessError) -> Self {
+        match e {
+            ProcessError::NotFound => io::Error::new(io::ErrorKind::NotFound, e.to_string()),
+            ProcessError::IsDirectory => io::Error::new(io::ErrorKind::IsADirectory, e.to_string()),
+            ProcessError::Encoding(e) | ProcessError::Io(e) => e,
+            ProcessError::Network(e) => io::Error::other(e),
+            ProcessError::Archive(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_error_variants() {
+        let err = ProcessError::NotFound;
+        assert_eq!(err.to_string(), "no such file or directory");
+        assert!(err.source().is_none());
+
+        let err = ProcessError::IsDirectory;
+        assert_eq!(err.to_string(), "is a directory");
+        assert!(err.source().is_none());
+
+        let err = ProcessError::Encoding(io::Error::new(io::ErrorKind::InvalidData, "bad gzip"));
+        assert_eq!(err.to_string(), "invalid data: bad gzip");
+        assert!(err.source().is_some());
+
+        let err = ProcessError::Io(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+        assert_eq!(err.to_string(), "denied");
+        assert!(err.source().is_some());
+
+        let err = ProcessError::Network(ureq::Error::StatusCode(404));
+        assert_eq!(err.to_string(), "could not fetch: http status: 404");
+        assert!(err.source().is_some());
+
+        let err = ProcessError::from(zip::result::ZipError::InvalidArchive("bad header".into()));
+        assert_eq!(err.to_string(), "invalid Zip archive: bad header");
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn test_process_error_from_io_error() {
+        let from = |kind| ProcessError::from(io::Error::new(kind, "error"));
+
+        assert!(matches!(from(io::ErrorKind::NotFound), ProcessError::NotFound));
+        assert!(matches!(from(io::ErrorKind::IsADirectory), ProcessError::IsDirectory));
+        assert!(matches!(from(io::ErrorKind::InvalidData), ProcessError::Encoding(_)));
+        assert!(matches!(from(io::ErrorKind::PermissionDenied), ProcessError::Io(_)));
+
+        // ZIPの読み込み中の入出力エラーは入出力エラーとして扱う
+        let err = zip::result::ZipError::Io(io::Error::new(io::ErrorKind::NotFound, "error"));
+        assert!(matches!(ProcessError::from(err), ProcessError::NotFound));
+    }
+}
@@ -0,0 +1,3364 @@
+//! テキストファイルの行数、単語数、文字数、バイト数をカウントするライブラリ
+//!
+//! コマンドラインツール `text-stats` の集計処理を提供する。
+
+mod error;
+mod metric;
+
+pub use error::ProcessError;
+pub use metric::{Metric, MetricFactory};
+
+use flate2::bufread::MultiGzDecoder;
+use glob::Pattern;
+use memmap2::Mmap;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::digest::DynDigest;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// テキストのカウント結果を保持する構造体
+/// `count_stats` などの集計関数はこの構造体を返すため、各項目は順序ではなく名前で参照する
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub struct Counts {
+    pub lines: usize,  // 行数
+    pub words: usize,  // 単語数
+    pub chars: usize,  // 文字数（Unicodeスカラー値の数）
+    pub chars_no_spaces: usize,  // 空白文字（改行を含む）を除いた文字数
+    pub bytes: usize,  // バイト数
+    pub max_line_length: usize,  // 最長行の文字数（改行を除く）
+    pub max_line_number: usize,  // 最長行の行番号（1始まり。同じ長さの場合は先の行。行がない場合は0）
+    pub min_line_length: usize,  // 最短行の文字数（改行を除く。行がない場合は0）
+    pub avg_line_length: f64,  // 1行あたりの平均文字数（改行を除く）
+    pub empty_lines: usize,  // 空行の数（改行以外に何も含まない行）
+    pub blank_lines: usize,  // 空白文字のみを含む行の数（空行は含まない）
+    pub trailing_ws_lines: usize,  // 末尾に空白文字のある行の数（改行を除く。空白のみの行も含む）
+    pub sentences: usize,  // 文の数（sentence_ends を参照）
+    pub paragraphs: usize,  // 段落の数（空行で区切られたブロックの数）
+    pub letters: usize,  // 文字（アルファベット・かな・漢字など）の数
+    pub digits: usize,  // 数字の数
+    pub whitespace: usize,  // 空白文字（改行を含む）の数
+    pub punctuation: usize,  // 上記以外（記号・制御文字など）の数
+    pub non_ascii: usize,  // ASCII以外の文字の数（不正なバイト列を置き換えた置換文字を含む）
+    pub is_binary: bool,  // 先頭 BINARY_SNIFF_LEN バイトにNULを含むバイナリファイルかどうか
+    pub avg_words_per_line: f64,  // 1行あたりの平均単語数
+    pub avg_chars_per_word: f64,  // 1単語あたりの平均文字数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub graphemes: Option<usize>,  // 拡張書記素クラスタの数（見た目上の文字数）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_width: Option<usize>,  // 端末上の表示幅の合計（改行を除く）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<usize>,  // 指定したパターンに一致した回数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matching_lines: Option<usize>,  // 指定したパターンに一致する行の数（`invert` の場合は一致しない行の数）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longest_word: Option<String>,  // 最も長い単語（同じ長さの場合は先に出現したもの）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shortest_word: Option<String>,  // 最も短い単語（同じ長さの場合は先に出現したもの）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_word_length: Option<f64>,  // 単語の平均文字数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unique_words: Option<usize>,  // 正規化した単語の種類の数（語彙数）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palindrome_lines: Option<usize>,  // 逆から読んでも同じ行の数（空白のみの行は含まない）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tab_indented: Option<usize>,  // タブのみで字下げした行の数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub space_indented: Option<usize>,  // 空白のみで字下げした行の数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mixed_indent_lines: Option<usize>,  // タブと空白を混ぜて字下げした行の数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crlf: Option<usize>,  // `\r\n` で終わる行の数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lf: Option<usize>,  // `\r` を伴わない `\n` で終わる行の数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cr: Option<usize>,  // `\n` を伴わない単独の `\r` の数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub char_counts: Option<BTreeMap<char, usize>>,  // 指定した文字ごとの出現回数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub word_length_counts: Option<BTreeMap<usize, usize>>,  // 単語の文字数ごとの単語の数（出現しない文字数は含まない）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_every: Option<usize>,  // 指定した場合、N行ごとに1行のみを数えた標本の結果であること
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extrapolated: Option<bool>,  // 標本の件数をN倍して全体を推定したかどうか
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, serde_json::Value>,  // 登録した追加の項目の名前ごとの値
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_offsets: Option<Vec<usize>>,  // 数えた各行の先頭のバイト位置（ファイルの先頭から）。合計には含めない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,  // 内容のダイジェスト（16進数の小文字）。合計には含めない
+    #[serde(skip)]
+    pub line_lengths: Vec<usize>,  // 各行の文字数（ヒストグラム用。集計する場合のみ）
+    #[serde(skip)]
+    pub observed_lines: usize,  // 実際に集計した行数（平均の計算用。`--count-newlines` では末尾に区切りのない最終行も含む）
+    #[serde(skip)]
+    pub line_chars: usize,  // 各行の文字数の合計（平均の計算用）
+    #[serde(skip)]
+    pub word_chars: usize,  // 単語に含まれる文字数の合計（平均の計算用）
+    #[serde(skip)]
+    pub word_frequencies: HashMap<String, usize>,  // 正規化した単語ごとの出現回数
+    #[serde(skip)]
+    pub lossy: bool,  // 不正なUTF-8を置換文字（U+FFFD）に置き換えたかどうか
+}
+
+impl Counts {
+    /// タブで字下げした行と空白で字下げした行が両方ある（または1行の中で混ぜている）かどうかを判定する関数
+    /// `indentation` を指定せずに数えた場合は常に `false`
+    pub fn mixes_indentation(&self) -> bool {
+        let count = |n: Option<usize>| n.unwrap_or(0);
+        count(self.mixed_indent_lines) > 0
+            || (count(self.tab_indented) > 0 && count(self.space_indented) > 0)
+    }
+
+    /// 行・単語・文字を1つも含まない（空のファイルの）カウント結果かどうかを判定する関数
+    pub fn is_empty(&self) -> bool {
+        self.lines == 0 && self.words == 0 && self.chars == 0
+    }
+
+    /// 件数を表す項目を `factor` 倍して、標本から全体の件数を推定する関数
+    /// 最長・最短・平均や単語の種類の数は標本から推定できないため、そのままにする
+    fn scale(&mut self, factor: usize) {
+        for count in [
+            &mut self.lines,
+            &mut self.words,
+            &mut self.chars,
+            &mut self.chars_no_spaces,
+            &mut self.bytes,
+            &mut self.empty_lines,
+            &mut self.blank_lines,
+            &mut self.trailing_ws_lines,
+            &mut self.sentences,
+            &mut self.paragraphs,
+            &mut self.letters,
+            &mut self.digits,
+            &mut self.whitespace,
+            &mut self.punctuation,
+            &mut self.non_ascii,
+            &mut self.observed_lines,
+            &mut self.line_chars,
+            &mut self.word_chars,
+        ] {
+            *count *= factor;
+        }
+        for count in [
+            &mut self.graphemes,
+            &mut self.display_width,
+            &mut self.matches,
+            &mut self.matching_lines,
+            &mut self.palindrome_lines,
+            &mut self.tab_indented,
+            &mut self.space_indented,
+            &mut self.mixed_indent_lines,
+            &mut self.crlf,
+            &mut self.lf,
+            &mut self.cr,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *count *= factor;
+        }
+        let maps = self
+            .char_counts
+            .iter_mut()
+            .flat_map(|map| map.values_mut())
+            .chain(self.word_length_counts.iter_mut().flat_map(|map| map.values_mut()))
+            .chain(self.word_frequencies.values_mut());
+        for count in maps {
+            *count *= factor;
+        }
+    }
+
+    /// 行数・単語数・文字数から平均値を計算し直す関数
+    /// 分母が0の場合は0.0とする
+    fn update_averages(&mut self) {
+        let ratio = |n: usize, d: usize| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+        self.avg_words_per_line = ratio(self.words, self.observed_lines);
+        self.avg_chars_per_word = ratio(self.chars, self.words);
+        self.avg_line_length = ratio(self.line_chars, self.observed_lines);
+        if self.avg_word_length.is_some() {
+            self.avg_word_length = Some(ratio(self.word_chars, self.words));
+        }
+    }
+}
+
+/// 入力ファイルの文字コード
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8（不正なバイト列は置換文字に置き換える）
+    #[default]
+    #[value(name = "utf-8", alias = "utf8")]
+    Utf8,
+    /// Latin-1（ISO-8859-1）
+    Latin1,
+    /// 行ごとにUTF-8として読めるか判定し、読めなければLatin-1として扱う
+    Auto,
+}
+
+impl Encoding {
+    /// 1行分のバイト列を文字列に変換する関数
+    /// UTF-8で不正なバイト列を置き換えた場合は `lossy` を立てる
+    fn decode<'a>(self, bytes: &'a [u8], lossy: &mut bool) -> Cow<'a, str> {
+        match (self, std::str::from_utf8(bytes)) {
+            (Encoding::Utf8 | Encoding::Auto, Ok(line)) => Cow::Borrowed(line),
+            (Encoding::Utf8, Err(_)) => {
+                *lossy = true;
+                String::from_utf8_lossy(bytes)
+            }
+            // Latin-1 は各バイトが同じ値のUnicodeスカラー値に対応する
+            (Encoding::Latin1, _) | (Encoding::Auto, Err(_)) => {
+                Cow::Owned(bytes.iter().map(|&b| char::from(b)).collect())
+            }
+        }
+    }
+}
+
+/// 数える前に適用するUnicodeの正規化形式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// 正準等価な文字を合成する（NFC）
+    Nfc,
+    /// 正準等価な文字を分解する（NFD）
+    Nfd,
+    /// 互換等価な文字を合成する（NFKC）
+    Nfkc,
+    /// 互換等価な文字を分解する（NFKD）
+    Nfkd,
+}
+
+impl Normalization {
+    /// 文字列を正規化する関数
+    fn apply(self, text: &str) -> String {
+        match self {
+            Normalization::Nfc => text.nfc().collect(),
+            Normalization::Nfd => text.nfd().collect(),
+            Normalization::Nfkc => text.nfkc().collect(),
+            Normalization::Nfkd => text.nfkd().collect(),
+        }
+    }
+}
+
+/// 内容のダイジェストを求めるハッシュ関数
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// MD5
+    Md5,
+    /// SHA-1
+    Sha1,
+    /// SHA-256
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// 新しいハッシュ関数の状態を作る関数
+    fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            HashAlgorithm::Md5 => Box::new(md5::Md5::default()),
+            HashAlgorithm::Sha1 => Box::new(sha1::Sha1::default()),
+            HashAlgorithm::Sha256 => Box::new(sha2::Sha256::default()),
+        }
+    }
+}
+
+/// カウント時の設定を保持する構造体
+#[derive(Debug, Default, Clone)]
+pub struct CountOptions {
+    pub word_frequencies: bool,  // 単語ごとの出現回数を集計するかどうか
+    pub unique_words: bool,  // 単語の種類の数を数えるかどうか（種類は出現回数の集計から求める）
+    pub case_sensitive: bool,  // 単語の出現回数・種類で大文字小文字を区別するかどうか
+    pub graphemes: bool,  // 書記素クラスタを数えるかどうか
+    pub display_width: bool,  // 表示幅を計算するかどうか
+    pub encoding: Encoding,  // 入力の文字コード
+    pub normalize: Option<Normalization>,  // 数える前に適用する正規化形式（None の場合は正規化しない）
+    pub strip_ansi: bool,  // 数える前に端末のエスケープシーケンス（ANSI CSI）を取り除くかどうか
+    pub decompress: bool,  // gzip圧縮された入力を展開してからカウントするかどうか
+    pub match_pattern: Option<Regex>,  // 出現回数を数えるパターン
+    pub overlapping: bool,  // パターンの一致を重なりも含めて数えるかどうか
+    pub count_lines_matching: Option<Regex>,  // 指定した場合、一致する行の数を数える
+    pub invert: bool,  // count_lines_matching で一致しない行の数を数えるかどうか
+    pub tab_width: Option<usize>,  // 行の長さ・表示幅でのタブ幅（None の場合は DEFAULT_TAB_WIDTH、0 の場合はタブを1文字として数える）
+    pub word_lengths: bool,  // 最長・最短の単語と単語の平均文字数を求めるかどうか
+    pub word_length_dist: bool,  // 単語の文字数ごとの単語の数を数えるかどうか
+    pub word_pattern: Option<Regex>,  // 単語とみなすパターン（None の場合は空白で区切る）
+    pub min_word_length: usize,  // 単語とみなす最小の文字数（これより短い単語は数えない）
+    pub mmap: bool,  // 通常のファイルをメモリマップして読み込むかどうか
+    pub buffer_size: Option<NonZeroUsize>,  // 読み込み用のバッファのバイト数（None の場合は DEFAULT_BUFFER_SIZE）
+    pub line_endings: bool,  // 改行コードの種類ごとの数を数えるかどうか
+    pub line_filter: Option<Regex>,  // 指定した場合、一致する行のみを集計する
+    pub line_lengths: bool,  // 各行の文字数を記録するかどうか
+    pub positions: bool,  // 各行の先頭のバイト位置を記録するかどうか
+    pub delimiter: Option<u8>,  // 行の区切りとするバイト（None の場合は改行）
+    pub count_newlines: bool,  // 行数を `wc -l` と同様に区切りの数として数えるかどうか（末尾に区切りのない最終行を数えない）
+    pub hash: Option<HashAlgorithm>,  // 内容のダイジェストを求めるハッシュ関数
+    pub metrics: Vec<MetricFactory>,  // ファイルごとに集計する追加の項目
+    pub count_chars: Vec<char>,  // 出現回数を数える文字（空の場合は数えない）
+    pub palindromes: bool,  // 逆から読んでも同じ行を数えるかどうか
+    pub indentation: bool,  // 行頭の字下げの種類（タブ・空白・混在）ごとの行数を数えるかどうか
+    pub reversed: bool,  // 各行の文字を逆順に並べ替えた内容を数えるかどうか
+    pub every_nth: Option<NonZeroUsize>,  // 指定した場合、N行ごとに1行（1, N+1, 2N+1, ...行目）のみを数える
+    pub extrapolate: bool,  // every_nth の標本の件数をN倍して全体を推定するかどうか
+    pub range: Option<Range<u64>>,  // 指定した場合、入力のこのバイト範囲のみを数える（終了位置は含まない）
+    pub zip_entries: Option<Pattern>,  // 指定した場合、ZIP・tarアーカイブ内の一致するファイルのみを数える
+    pub retries: u32,  // URLの取得が一時的なエラーで失敗した場合に再試行する回数
+    pub retry_delay: Duration,  // 再試行するまでの待ち時間（再試行するたびに倍にする）
+    pub timeout: Option<Duration>,  // URLの取得を打ち切るまでの時間（None の場合は DEFAULT_TIMEOUT）
+}
+
+/// ファイルの統計情報を保持する構造体
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct Stats {
+    pub filename: String,  // ファイル名
+    #[serde(flatten)]
+    pub counts: Counts,    // カウント結果
+}
+
+/// 行末の改行（`\n` または `\r\n`）を取り除く関数
+fn strip_newline(line: &str) -> &str {
+    match line.strip_suffix('\n') {
+        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+        None => line,
+    }
+}
+
+/// 単語の出現回数を集計するために単語を正規化する関数
+/// 前後の記号を取り除き、`ignore_case` が真の場合は小文字にする。記号のみの単語は `None` を返す
+pub fn normalize_word(word: &str, ignore_case: bool) -> Option<String> {
+    let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+    if word.is_empty() {
+        None
+    } else if ignore_case {
+        Some(word.to_lowercase())
+    } else {
+        Some(word.to_string())
+    }
+}
+
+/// 末尾のピリオドを文末とみなさない略語（大文字小文字は区別しない）
+const ABBREVIATIONS: [&str; 9] = ["mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs"];
+
+/// 単語に含まれる文末の数を返す関数
+///
+/// 文末は次のように判定する。
+/// * `.` `!` `?` で終わる単語（直後が空白か末尾）を1つの文末とする。
+///   `?!` や `...` のように連続する場合も1つと数え、閉じ括弧や引用符は無視する。
+/// * ただし `e.g.` `i.e.` のように末尾以外にもピリオドを含む単語や、
+///   `Mr.` `Dr.` などの敬称（ABBREVIATIONS）がピリオドで終わる場合は略語とみなす。
+/// * 日本語の `。` `！` `？` は空白を挟まずに続くため、単語の途中でも連続ごとに1つと数える。
+fn sentence_ends(word: &str) -> usize {
+    let is_fullwidth = |c: char| matches!(c, '。' | '！' | '？');
+    let is_ascii = |c: char| matches!(c, '.' | '!' | '?');
+
+    // 全角の文末記号の連続を数える
+    let mut ends = 0;
+    let mut prev_is_end = false;
+    for c in word.chars() {
+        let is_end = is_fullwidth(c);
+        if is_end && !prev_is_end {
+            ends += 1;
+        }
+        prev_is_end = is_end;
+    }
+
+    let body = word.trim_end_matches(['"', '\'', ')', ']', '」', '』', '）']);
+    let stem = body.trim_end_matches(is_ascii);
+    if stem.len() < body.len() && !body.ends_with(is_fullwidth) {
+        let only_period = body[stem.len()..].chars().all(|c| c == '.');
+        let is_abbreviation = only_period
+            && (stem.contains('.') || ABBREVIATIONS.contains(&stem.to_lowercase().as_str()));
+        if !is_abbreviation {
+            ends += 1;
+        }
+    }
+
+    ends
+}
+
+/// 出現回数の多い順に上位 `n` 件の単語を返す関数
+/// 出現回数が同じ場合は単語のアルファベット順に並べる
+pub fn top_words(frequencies: &HashMap<String, usize>, n: usize) -> Vec<(String, usize)> {
+    let mut words: Vec<(String, usize)> = frequencies
+        .iter()
+        .map(|(word, &count)| (word.clone(), count))
+        .collect();
+    words.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    words.truncate(n);
+    words
+}
+
+/// 行の文字数を `bucket_size` ごとの範囲に分けて、範囲ごとの行数を返す関数
+///
+/// `i` 番目の範囲は `i * bucket_size + 1` 以上 `(i + 1) * bucket_size` 以下で、
+/// 先頭の範囲のみ文字数0の行も含む（`bucket_size` が10なら 0-10, 11-20, ...）。
+/// 行のない範囲も途中にあれば0として含め、最後の範囲は最長の行を含むものになる。
+pub fn line_length_histogram(line_lengths: &[usize], bucket_size: usize) -> Vec<usize> {
+    assert!(bucket_size > 0, "bucket size must be positive");
+    let mut buckets = Vec::new();
+    for &length in line_lengths {
+        let index = length.saturating_sub(1) / bucket_size;
+        if buckets.len() <= index {
+            buckets.resize(index + 1, 0);
+        }
+        buckets[index] += 1;
+    }
+    buckets
+}
+
+/// 文字列中でパターンに一致した回数を返す関数
+/// `overlapping` が真の場合は一致の終わりではなく開始位置の次の文字から探し直すため、
+/// `aaaa` の中の `aa` は重ならない場合の2回ではなく3回と数える
+fn count_matches(pattern: &Regex, text: &str, overlapping: bool) -> usize {
+    if !overlapping {
+        return pattern.find_iter(text).count();
+    }
+    let mut count = 0;
+    let mut start = 0;
+    while let Some(m) = pattern.find_at(text, start) {
+        count += 1;
+        // 末尾での空文字列への一致の後は探す位置がなくなる
+        match text[m.start()..].chars().next() {
+            Some(c) => start = m.start() + c.len_utf8(),
+            None => break,
+        }
+    }
+    count
+}
+
+/// タブを次のタブ位置まで展開したときの行の長さを返す関数
+/// タブ以外の部分の長さは `measure` で測る
+fn expanded_length(content: &str, tab_width: usize, measure: impl Fn(&str) -> usize) -> usize {
+    if tab_width == 0 {
+        return measure(content);
+    }
+    let mut column = 0;
+    for (i, segment) in content.split('\t').enumerate() {
+        if i > 0 {
+            column += tab_width - column % tab_width;
+        }
+        column += measure(segment);
+    }
+    column
+}
+
+/// 端末の色やカーソル移動に使うANSI CSIエスケープシーケンス（`ESC [` 引数 中間バイト 終端バイト）
+static ANSI_CSI: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]").expect("valid ANSI CSI pattern"));
+
+/// バイナリファイルかどうかを判定するために調べる先頭のバイト数
+pub const BINARY_SNIFF_LEN: usize = 8192;
+
+/// ストリームから読み込むときのバッファのデフォルトのバイト数
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 行の長さ・表示幅を測るときのデフォルトのタブ幅（コマンドラインの `--tab-width` のデフォルトと同じ）
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// URLの取得（本文の読み込みまで）のデフォルトのタイムアウト
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// テキストの統計情報をデフォルトの設定でカウントする関数
+pub fn count_stats<R: Read>(reader: R) -> io::Result<Counts> {
+    count_stats_with_options(reader, &CountOptions::default())
+}
+
+/// メモリ上の文字列の統計情報をデフォルトの設定でカウントする関数
+/// 読み込みを行わないため失敗することはなく、`count_stats` と同じ結果になる
+pub fn count_str(text: &str) -> Counts {
+    count_bytes_with_options(text.as_bytes(), &CountOptions::default())
+}
+
+/// 1行ずつ受け取りながら統計情報を作る集計器
+/// ストリームからの読み込みとメモリマップしたバイト列の両方で同じ集計を行う。
+/// ソケットやジェネレータなど `Read` で読めない入力も、行ごとに `push_line` で渡せば同じように数えられる
+pub struct StatsBuilder<'a> {
+    options: &'a CountOptions,
+    counts: Counts,
+    in_paragraph: bool,  // 直前の行が段落の途中かどうか
+    unterminated: bool,  // 最後に数えた行が区切りで終わっていないかどうか
+    longest_len: usize,  // これまでの最長の単語の文字数
+    shortest_len: usize,  // これまでの最短の単語の文字数
+    hasher: Option<Box<dyn DynDigest>>,  // 読み込んだバイト列のダイジェストを計算する状態
+    read_bytes: usize,  // 絞り込みで読み飛ばした行も含めて読み込んだバイト数
+    read_lines: usize,  // 標本に含めなかった行も含めて読み込んだ行数
+    metrics: Vec<Box<dyn Metric>>,  // 追加の項目の集計器
+}
+
+impl<'a> StatsBuilder<'a> {
+    /// 指定した設定で数える空の集計器を作る関数
+    pub fn new(options: &'a CountOptions) -> Self {
+        let mut counts = Counts::default();
+        if options.word_lengths {
+            counts.longest_word = Some(String::new());
+            counts.shortest_word = Some(String::new());
+            counts.avg_word_length = Some(0.0);
+        }
+        if options.unique_words {
+            counts.unique_words = Some(0);
+        }
+        if options.word_length_dist {
+            counts.word_length_counts = Some(BTreeMap::new());
+        }
+        if options.count_lines_matching.is_some() {
+            counts.matching_lines = Some(0);
+        }
+        if options.palindromes {
+            counts.palindrome_lines = Some(0);
+        }
+        if options.indentation {
+            counts.tab_indented = Some(0);
+            counts.space_indented = Some(0);
+            counts.mixed_indent_lines = Some(0);
+        }
+        if !options.count_chars.is_empty() {
+            // 一度も出現しない文字も0回として含める
+            counts.char_counts = Some(options.count_chars.iter().map(|&c| (c, 0)).collect());
+        }
+        if options.positions {
+            counts.line_offsets = Some(Vec::new());
+        }
+        if options.line_endings {
+            counts.crlf = Some(0);
+            counts.lf = Some(0);
+            counts.cr = Some(0);
+        }
+        StatsBuilder {
+            options,
+            counts,
+            in_paragraph: false,
+            unterminated: false,
+            longest_len: 0,
+            shortest_len: usize::MAX,
+            hasher: options.hash.map(HashAlgorithm::hasher),
+            read_bytes: 0,
+            read_lines: 0,
+            metrics: options.metrics.iter().map(|factory| factory()).collect(),
+        }
+    }
+
+    /// 1行分を集計する関数
+    /// `line` は行末の改行（`delimiter` を指定した場合はその区切り）を含めて渡す（最終行は改行なしでもよい）。
+    /// `str::split_inclusive` や `BufRead::read_line` で分けた行をそのまま渡せば、全体を数えた場合と同じ結果になる
+    pub fn push_line(&mut self, line: &str) {
+        self.add_line(line.as_bytes());
+    }
+
+    /// これまでに渡した行の統計情報をファイル名を付けて返す関数
+    pub fn build(self, filename: String) -> Stats {
+        Stats {
+            filename,
+            counts: self.finish(),
+        }
+    }
+
+    /// 改行を含む1行分（最終行は改行なしでもよい）のバイト列を集計する
+    fn add_line(&mut self, buf: &[u8]) {
+        // ダイジェストは絞り込みによらずファイルの内容全体から求める
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(buf);
+        }
+        // NULを区切りにした場合はNULを含んでいてもテキストとみなす
+        if self.read_bytes < BINARY_SNIFF_LEN && self.options.delimiter != Some(b'\0') {
+            let head = &buf[..buf.len().min(BINARY_SNIFF_LEN - self.read_bytes)];
+            self.counts.is_binary |= head.contains(&0);
+        }
+        let offset = self.read_bytes;
+        self.read_bytes += buf.len();
+        self.read_lines += 1;
+        // 標本に含めない行は文字列に変換せずに読み飛ばす
+        if let Some(n) = self.options.every_nth {
+            if !(self.read_lines - 1).is_multiple_of(n.get()) {
+                return;
+            }
+        }
+        let line = self.options.encoding.decode(buf, &mut self.counts.lossy);
+        let mut line = line.as_ref();
+        // 正規化しても改行や区切りは変わらないため、行ごとに正規化してよい
+        let normalized: String;
+        if let Some(form) = self.options.normalize {
+            normalized = form.apply(line);
+            line = &normalized;
+        }
+        // エスケープシーケンスは改行を含まないため、行ごとに取り除いてよい
+        let stripped: Cow<str>;
+        if self.options.strip_ansi {
+            stripped = ANSI_CSI.replace_all(line, "");
+            line = &stripped;
+        }
+        let mut content = self.strip_delimiter(line);
+        // 行末の区切りはそのままにして、行の内容の文字のみを逆順にする
+        let reversed: String;
+        if self.options.reversed {
+            reversed = content.chars().rev().chain(line[content.len()..].chars()).collect();
+            line = &reversed;
+            content = &reversed[..content.len()];
+        }
+        // 一致しない行は読み飛ばし、どの項目にも数えない
+        if let Some(filter) = &self.options.line_filter {
+            if !filter.is_match(content) {
+                return;
+            }
+        }
+
+        self.counts.lines += 1;
+        self.counts.observed_lines += 1;
+        self.unterminated = content.len() == line.len();
+        if let Some(offsets) = &mut self.counts.line_offsets {
+            // `range` を指定した場合も、範囲の先頭ではなくファイルの先頭からの位置にする
+            let start = self.options.range.as_ref().map_or(0, |range| range.start);
+            offsets.push(usize::try_from(start).unwrap_or(usize::MAX).saturating_add(offset));
+        }
+        for metric in &mut self.metrics {
+            metric.observe(line);
+        }
+        // 文字・空白区切りの単語・文の区切りは行を1回走査する間に数える
+        // 単語ごとの処理が必要な場合のみ、改めて単語を取り出す
+        let inline_words = !self.needs_word_iter();
+        let mut token_start = None;  // 走査中の空白区切りのトークンの開始位置
+        let mut content_chars = 0;  // 区切りを除いた行の内容の文字数
+        let mut has_tab = false;
+        for (i, c) in line.char_indices() {
+            self.counts.chars += 1;
+            // 4つの分類の合計は文字数と一致する
+            if c.is_alphabetic() {
+                self.counts.letters += 1;
+            } else if c.is_numeric() {
+                self.counts.digits += 1;
+            } else if c.is_whitespace() {
+                self.counts.whitespace += 1;
+            } else {
+                self.counts.punctuation += 1;
+            }
+            if !c.is_ascii() {
+                self.counts.non_ascii += 1;
+            }
+            if !c.is_whitespace() {
+                self.counts.chars_no_spaces += 1;
+            }
+            if let Some(count) = self.counts.char_counts.as_mut().and_then(|map| map.get_mut(&c)) {
+                *count += 1;
+            }
+            // 行末の区切りは行の内容に含めないため、空白でなくてもトークンの区切りとする
+            let in_content = i < content.len();
+            if in_content {
+                content_chars += 1;
+                has_tab |= c == '\t';
+            }
+            match (token_start, in_content && !c.is_whitespace()) {
+                (None, true) => token_start = Some(i),
+                (Some(start), false) => {
+                    self.end_token(&content[start..i], inline_words);
+                    token_start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = token_start {
+            self.end_token(&content[start..], inline_words);
+        }
+        if !inline_words {
+            self.add_words(content);
+        }
+        self.counts.bytes += buf.len();  // 変換前のバイト数
+        if self.options.line_endings {
+            // 行は `\n` で区切るため、単独の `\r` は行の途中か最終行の末尾にしか現れない
+            let body = if let Some(body) = buf.strip_suffix(b"\r\n") {
+                *self.counts.crlf.get_or_insert(0) += 1;
+                body
+            } else if let Some(body) = buf.strip_suffix(b"\n") {
+                *self.counts.lf.get_or_insert(0) += 1;
+                body
+            } else {
+                buf
+            };
+            *self.counts.cr.get_or_insert(0) += body.iter().filter(|&&b| b == b'\r').count();
+        }
+        if self.options.graphemes {
+            // 改行の直後は必ず書記素の境界になるため、行ごとに数えてよい
+            *self.counts.graphemes.get_or_insert(0) += line.graphemes(true).count();
+        }
+        let line_length = if has_tab {
+            expanded_length(content, self.tab_width(), |s| s.chars().count())
+        } else {
+            content_chars
+        };
+        // 最長・最短・平均はいずれも同じ行の長さから求める
+        // 絞り込んだ場合は `grep` で抜き出した結果と同じく、一致した行の中での行番号とする
+        if self.counts.lines == 1 || line_length > self.counts.max_line_length {
+            self.counts.max_line_length = line_length;
+            self.counts.max_line_number = self.counts.lines;
+        }
+        if self.counts.lines == 1 || line_length < self.counts.min_line_length {
+            self.counts.min_line_length = line_length;
+        }
+        self.counts.line_chars += line_length;
+        if self.options.line_lengths {
+            self.counts.line_lengths.push(line_length);
+        }
+        if let Some(pattern) = &self.options.match_pattern {
+            // 行ごとに照合するため、改行をまたぐパターンには一致しない
+            let matches = count_matches(pattern, content, self.options.overlapping);
+            *self.counts.matches.get_or_insert(0) += matches;
+        }
+        if let Some(pattern) = &self.options.count_lines_matching {
+            if pattern.is_match(content) != self.options.invert {
+                *self.counts.matching_lines.get_or_insert(0) += 1;
+            }
+        }
+        if self.options.display_width {
+            // 全角文字は2列、結合文字は0列として数える
+            let width = expanded_length(content, self.tab_width(), |s| s.width());
+            *self.counts.display_width.get_or_insert(0) += width;
+        }
+        let is_palindrome = || content.chars().eq(content.chars().rev());
+        if self.options.palindromes && !content.trim().is_empty() && is_palindrome() {
+            *self.counts.palindrome_lines.get_or_insert(0) += 1;
+        }
+        // 空白のみの行は字下げとみなさない
+        if self.options.indentation && !content.trim().is_empty() {
+            let indent = content.chars().take_while(|&c| c == ' ' || c == '\t');
+            let (tabs, spaces) = indent.fold((false, false), |(tabs, spaces), c| {
+                (tabs || c == '\t', spaces || c == ' ')
+            });
+            let count = match (tabs, spaces) {
+                (true, true) => self.counts.mixed_indent_lines.as_mut(),
+                (true, false) => self.counts.tab_indented.as_mut(),
+                (false, true) => self.counts.space_indented.as_mut(),
+                (false, false) => None,  // 字下げしていない行
+            };
+            if let Some(count) = count {
+                *count += 1;
+            }
+        }
+        if content != content.trim_end() {
+            self.counts.trailing_ws_lines += 1;
+        }
+        if content.is_empty() {
+            self.counts.empty_lines += 1;
+        } else if content.trim().is_empty() {
+            self.counts.blank_lines += 1;
+        }
+        // 空行・空白のみの行の後に続く行から新しい段落が始まる
+        let is_blank = content.trim().is_empty();
+        if !is_blank && !self.in_paragraph {
+            self.counts.paragraphs += 1;
+        }
+        self.in_paragraph = !is_blank;
+    }
+
+    /// 単語の出現回数や長さなど、単語ごとの処理が必要かどうか
+    /// 不要な場合は空白区切りの単語の数のみを文字の走査と同時に数える
+    fn needs_word_iter(&self) -> bool {
+        self.options.word_pattern.is_some()
+            || self.options.min_word_length > 1
+            || self.options.word_lengths
+            || self.options.word_length_dist
+            || self.options.word_frequencies
+            || self.options.unique_words
+    }
+
+    /// 空白区切りのトークンの終わりで、文の区切りと（`count_word` の場合は）単語を数える
+    /// 文の区切りは単語の定義によらず空白区切りのトークンの末尾で判定する
+    fn end_token(&mut self, token: &str, count_word: bool) {
+        self.counts.sentences += sentence_ends(token);
+        if count_word {
+            self.counts.words += 1;
+        }
+    }
+
+    /// 行の内容から単語を取り出し、単語の数・長さ・出現回数を集計する
+    fn add_words(&mut self, content: &str) {
+        let words: Box<dyn Iterator<Item = &str>> = match &self.options.word_pattern {
+            Some(pattern) => Box::new(pattern.find_iter(content).map(|m| m.as_str())),
+            None => Box::new(content.split_whitespace()),
+        };
+        let min_length = self.options.min_word_length;
+        for word in words {
+            // 短い単語は単語の数・出現回数・単語の長さのいずれにも数えない
+            if min_length > 0 && word.chars().nth(min_length - 1).is_none() {
+                continue;
+            }
+            self.counts.words += 1;
+            if let Some(dist) = &mut self.counts.word_length_counts {
+                *dist.entry(word.chars().count()).or_insert(0) += 1;
+            }
+            if self.options.word_lengths {
+                // 同じ長さの場合は先に出現した単語を残す
+                let len = word.chars().count();
+                self.counts.word_chars += len;
+                if len > self.longest_len {
+                    self.longest_len = len;
+                    self.counts.longest_word = Some(word.to_string());
+                }
+                if len < self.shortest_len {
+                    self.shortest_len = len;
+                    self.counts.shortest_word = Some(word.to_string());
+                }
+            }
+            if self.options.word_frequencies || self.options.unique_words {
+                if let Some(word) = normalize_word(word, !self.options.case_sensitive) {
+                    *self.counts.word_frequencies.entry(word).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    /// 行の長さ・表示幅でのタブ幅
+    fn tab_width(&self) -> usize {
+        self.options.tab_width.unwrap_or(DEFAULT_TAB_WIDTH)
+    }
+
+    /// 行末の区切り（デフォルトでは改行）を取り除く
+    fn strip_delimiter<'l>(&self, line: &'l str) -> &'l str {
+        match self.options.delimiter {
+            None | Some(b'\n') => strip_newline(line),
+            Some(delimiter) => line.strip_suffix(char::from(delimiter)).unwrap_or(line),
+        }
+    }
+
+    fn finish(mut self) -> Counts {
+        // 区切りで終わっていない行は最終行のみ
+        // 平均は実際に集計した行から計算するため `observed_lines` は減らさない
+        if self.options.count_newlines && self.unterminated {
+            self.counts.lines -= 1;
+        }
+        if self.options.unique_words {
+            self.counts.unique_words = Some(self.counts.word_frequencies.len());
+        }
+        for metric in self.metrics {
+            let (name, value) = metric.finish();
+            self.counts.metrics.insert(name, value);
+        }
+        if let Some(n) = self.options.every_nth {
+            self.counts.sample_every = Some(n.get());
+            self.counts.extrapolated = Some(self.options.extrapolate);
+            if self.options.extrapolate {
+                self.counts.scale(n.get());
+            }
+        }
+        if let Some(hasher) = self.hasher {
+            let digest = hasher.finalize();
+            self.counts.hash = Some(digest.iter().map(|b| format!("{b:02x}")).collect());
+        }
+        self.counts.update_averages();
+        self.counts
+    }
+}
+
+/// テキストの統計情報をカウントする関数
+/// ファイル全体をメモリに読み込まず、1行ずつ読み込みながらカウントする
+///
+/// 改行は空白文字なので単語が行をまたぐことはなく、行単位で数えても
+/// バッファの境界で単語が二重にカウントされることはない。
+pub fn count_stats_with_options<R: Read>(reader: R, options: &CountOptions) -> io::Result<Counts> {
+    let capacity = options.buffer_size.map_or(DEFAULT_BUFFER_SIZE, NonZeroUsize::get);
+    let mut reader = BufReader::with_capacity(capacity, reader);
+    let mut builder = StatsBuilder::new(options);
+    let mut buf = Vec::new();
+    let delimiter = options.delimiter.unwrap_or(b'\n');
+
+    loop {
+        buf.clear();
+        // 区切りまで（最終行は末尾まで）を読み込む
+        if reader.read_until(delimiter, &mut buf)? == 0 {
+            break;
+        }
+        builder.add_line(&buf);
+    }
+
+    Ok(builder.finish())
+}
+
+/// メモリ上のバイト列の統計情報をカウントする関数
+/// `count_stats_with_options` と同じ結果になるが、行をバッファにコピーせずに数える
+pub fn count_bytes_with_options(bytes: &[u8], options: &CountOptions) -> Counts {
+    let mut builder = StatsBuilder::new(options);
+    let delimiter = options.delimiter.unwrap_or(b'\n');
+    for line in bytes.split_inclusive(|&b| b == delimiter) {
+        builder.add_line(line);
+    }
+    builder.finish()
+}
+
+/// gzipのマジックナンバー
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// 拡張子が `.gz` であるか、先頭 `head` がgzipのマジックナンバーであれば圧縮されているとみなす関数
+fn is_gzip(path: &Path, head: &[u8]) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz") || head.starts_with(&GZIP_MAGIC)
+}
+
+/// 入力がgzip圧縮されていれば展開するリーダーで包む関数
+fn maybe_decompress<'a>(
+    reader: Box<dyn Read + 'a>,
+    path: &Path,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    if is_gzip(path, reader.fill_buf()?) {
+        // 複数のメンバーを連結したファイルも `zcat` と同様に全て展開する
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// 開始位置まで読み飛ばし、終了位置までを読むリーダーで包む関数
+/// 入力が範囲より短い場合は入力の末尾までとなる
+fn read_range<'a>(
+    mut reader: Box<dyn Read + 'a>,
+    range: &Range<u64>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    io::copy(&mut reader.by_ref().take(range.start), &mut io::sink())?;
+    Ok(Box::new(reader.take(range.end.saturating_sub(range.start))))
+}
+
+/// バイト範囲を長さ `len` のバイト列の添字の範囲に収める関数
+fn clamp_range(range: &Range<u64>, len: usize) -> Range<usize> {
+    let clamp = |n: u64| usize::try_from(n).map_or(len, |n| n.min(len));
+    let start = clamp(range.start);
+    start..clamp(range.end).max(start)
+}
+
+/// パスが `http://` または `https://` で始まるURLかどうかを判定する関数
+pub fn is_url(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// 再試行すれば成功する可能性のある一時的なネットワークのエラーかどうかを判定する関数
+/// 接続やタイムアウトのエラー、サーバー側のエラー（5xx）と `429 Too Many Requests` を一時的なエラーとする
+fn is_transient(err: &ureq::Error) -> bool {
+    match err {
+        ureq::Error::StatusCode(status) => *status == 429 || *status >= 500,
+        ureq::Error::Io(_)
+        | ureq::Error::Timeout(_)
+        | ureq::Error::ConnectionFailed
+        | ureq::Error::BodyStalled => true,
+        _ => false,
+    }
+}
+
+/// 本文の読み込み中のエラーのうち、ネットワークによるエラーを取り出す関数
+/// 接続の切断やタイムアウトはネットワークのエラーとし、展開の失敗など本文の内容によるエラーはそのまま返す
+fn network_error(err: io::Error) -> Result<ureq::Error, io::Error> {
+    if err.get_ref().is_some_and(|inner| inner.is::<ureq::Error>()) {
+        let inner = err.into_inner().expect("checked above");
+        return Ok(*inner.downcast::<ureq::Error>().expect("checked above"));
+    }
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::TimedOut => Ok(ureq::Error::Io(err)),
+        _ => Err(err),
+    }
+}
+
+/// URLを取得し、本文を `read` で読み込む関数
+/// 取得または本文の読み込みが一時的なエラーで失敗した場合は `options.retries` 回まで、
+/// 待ち時間を倍にしながら取得し直して `read` で最初から読み直す。
+/// 応答しないサーバーで処理が止まらないよう、本文の読み込みまでを `options.timeout` で打ち切る
+fn with_url<T>(
+    url: &str,
+    options: &CountOptions,
+    mut read: impl FnMut(Box<dyn Read>) -> io::Result<T>,
+) -> Result<T, ProcessError> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(options.timeout.unwrap_or(DEFAULT_TIMEOUT)))
+        .build()
+        .into();
+    let mut delay = options.retry_delay;
+    let mut attempt = 0;
+    loop {
+        let err = match agent.get(url).call() {
+            Ok(response) => match read(Box::new(response.into_body().into_reader())) {
+                Ok(value) => return Ok(value),
+                Err(err) => network_error(err)?,
+            },
+            Err(err) => err,
+        };
+        if attempt < options.retries && is_transient(&err) {
+            attempt += 1;
+            thread::sleep(delay);
+            delay = delay.saturating_mul(2);
+        } else {
+            return Err(ProcessError::Network(err));
+        }
+    }
+}
+
+/// ファイルを処理して統計情報を取得する関数
+/// 標準入力やURLも1行ずつ読み込みながら数えるため、終わりのないストリームでも内容全体をメモリに保持せず、
+/// 不正なバイト列は `options.encoding` に従って行ごとに変換する
+/// 
+/// # 引数
+/// * `path` - 処理するファイルのパス（`-` で標準入力、`http://` `https://` で始まる場合はURL）
+/// * `options` - カウント時の設定
+/// 
+/// # 戻り値
+/// * `Stats` - ファイルの統計情報
+/// * `ProcessError` - ファイルが存在しない・ディレクトリである等、失敗した理由
+pub fn process_file(path: &Path, options: &CountOptions) -> Result<Stats, ProcessError> {
+    let filename = path.to_string_lossy().to_string();
+    // 標準入力の場合はstdin、URLの場合はレスポンスの本文を使用し、それ以外はファイルを開く
+    let reader: Box<dyn Read> = if path.to_string_lossy() == "-" {
+        Box::new(io::stdin().lock())
+    } else if is_url(path) {
+        // 読み込みの途中で失敗した場合は取得し直して数え直す
+        let counts = with_url(&filename, options, |body| count_reader(body, path, options))?;
+        return Ok(Stats { filename, counts });
+    } else {
+        // ディレクトリは開けてしまうため、読み込む前に明示的にエラーにする
+        let metadata = fs::metadata(path)?;
+        if metadata.is_dir() {
+            return Err(ProcessError::IsDirectory);
+        }
+        let file = File::open(path)?;
+        // 名前付きパイプやデバイスファイルはマップできないため通常の読み込みにする
+        if options.mmap && metadata.is_file() {
+            // SAFETY: 読み込み中に他のプロセスがファイルを書き換えると結果は不定になるが、
+            // マップした領域はカウントの間だけ読み取り専用で参照する
+            let map = unsafe { Mmap::map(&file)? };
+            // 圧縮されている場合は展開しながら読む必要があるためストリームで数える
+            if !(options.decompress && is_gzip(path, &map)) {
+                let bytes = match &options.range {
+                    Some(range) => &map[clamp_range(range, map.len())],
+                    None => &map[..],
+                };
+                let counts = count_bytes_with_options(bytes, options);
+                return Ok(Stats { filename, counts });
+            }
+        }
+        let mut reader = BufReader::new(file);
+        // 圧縮されていない通常のファイルは、開始位置まで読み飛ばさずにシークする
+        if let Some(range) = &options.range {
+            if metadata.is_file() && !(options.decompress && is_gzip(path, reader.fill_buf()?)) {
+                reader.seek(SeekFrom::Start(range.start))?;
+                let reader = reader.take(range.end.saturating_sub(range.start));
+                let counts = count_stats_with_options(reader, options)?;
+                return Ok(Stats { filename, counts });
+            }
+        }
+        Box::new(reader)
+    };
+    let counts = count_reader(reader, path, options)?;
+    Ok(Stats { filename, counts })
+}
+
+/// 先頭から読むリーダーを、必要に応じて展開して範囲を切り出してから数える関数
+fn count_reader(reader: Box<dyn Read>, path: &Path, options: &CountOptions) -> io::Result<Counts> {
+    let reader = if options.decompress {
+        maybe_decompress(reader, path)?
+    } else {
+        reader
+    };
+    // 標準入力や圧縮されたファイルはシークできないため、展開後の内容を開始位置まで読み飛ばす
+    let reader = match &options.range {
+        Some(range) => read_range(reader, range)?,
+        None => reader,
+    };
+    count_stats_with_options(reader, options)
+}
+
+/// 拡張子が `.zip` であればZIPアーカイブとみなす関数（大文字小文字は区別しない）
+pub fn is_zip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip")) && !is_url(path)
+}
+
+/// ZIPアーカイブに含まれる各ファイルを処理して統計情報を取得する関数
+///
+/// ファイル名は `archive.zip:entry.txt` の形式で、アーカイブ内の順序のまま返す。
+/// ディレクトリと、`zip_entries` を指定した場合にアーカイブ内のパスが一致しないファイルは含めない。
+pub fn process_zip(path: &Path, options: &CountOptions) -> Result<Vec<Stats>, ProcessError> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut stats = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name()?.into_owned();
+        if let Some(pattern) = &options.zip_entries {
+            if !pattern.matches(&name) {
+                continue;
+            }
+        }
+        let filename = format!("{}:{}", path.to_string_lossy(), name);
+        let reader = match &options.range {
+            Some(range) => read_range(Box::new(entry), range)?,
+            None => Box::new(entry),
+        };
+        let counts = count_stats_with_options(reader, options)?;
+        stats.push(Stats { filename, counts });
+    }
+    Ok(stats)
+}
+
+/// tarアーカイブ（拡張子 `.tar`・`.tar.gz`・`.tgz`）かどうかを判定する関数
+pub fn is_tar(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    [".tar", ".tar.gz", ".tgz"].iter().any(|ext| name.ends_with(ext)) && !is_url(path)
+}
+
+/// tarアーカイブに含まれる各ファイルを処理して統計情報を取得する関数
+/// gzip圧縮されたアーカイブは展開しながら読む
+///
+/// ファイル名は `archive.tar:entry.txt` の形式で、アーカイブ内の順序のまま返す。
+/// 通常のファイルのみを数え、ディレクトリやシンボリックリンクなどは含めない。
+/// `zip_entries` を指定した場合はZIPアーカイブと同じくアーカイブ内のパスが一致するファイルのみを数える。
+pub fn process_tar(path: &Path, options: &CountOptions) -> Result<Vec<Stats>, ProcessError> {
+    let reader = maybe_decompress(Box::new(File::open(path)?), path)?;
+    let mut archive = tar::Archive::new(reader);
+    let mut stats = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if let Some(pattern) = &options.zip_entries {
+            if !pattern.matches(&name) {
+                continue;
+            }
+        }
+        let filename = format!("{}:{}", path.to_string_lossy(), name);
+        let reader = match &options.range {
+            Some(range) => read_range(Box::new(entry), range)?,
+            None => Box::new(entry),
+        };
+        let counts = count_stats_with_options(reader, options)?;
+        stats.push(Stats { filename, counts });
+    }
+    Ok(stats)
+}
+
+/// 入力を処理して統計情報を取得する関数
+/// ZIPアーカイブ・tarアーカイブは展開して含まれるファイルごとに、それ以外は `process_file` で1件として返す
+pub fn process_input(path: &Path, options: &CountOptions) -> Result<Vec<Stats>, ProcessError> {
+    if options.decompress && is_zip(path) {
+        process_zip(path, options)
+    } else if options.decompress && is_tar(path) {
+        process_tar(path, options)
+    } else {
+        process_file(path, options).map(|stat| vec![stat])
+    }
+}
+
+/// 入力を開き、gzip圧縮されていれば展開するリーダーを返す関数
+/// `process_file` と異なり、メモリマップやシークは行わずに先頭から読む
+fn open_input(path: &Path, options: &CountOptions) -> Result<Box<dyn Read>, ProcessError> {
+    let reader: Box<dyn Read> = if path.to_string_lossy() == "-" {
+        Box::new(io::stdin().lock())
+    } else if is_url(path) {
+        // 他の入力と連結して読むため途中から読み直せない。再試行できるよう本文をまとめて読み込む
+        let body = with_url(&path.to_string_lossy(), options, |mut body| {
+            let mut bytes = Vec::new();
+            body.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        })?;
+        Box::new(io::Cursor::new(body))
+    } else {
+        if fs::metadata(path)?.is_dir() {
+            return Err(ProcessError::IsDirectory);
+        }
+        Box::new(File::open(path)?)
+    };
+    if options.decompress {
+        Ok(maybe_decompress(reader, path)?)
+    } else {
+        Ok(reader)
+    }
+}
+
+/// `process_file` が数える内容（gzipは展開後、`range` を指定した場合はその範囲）をメモリに読み込む関数
+/// 同じ内容を繰り返し数えて処理時間を計測する場合などに使う
+pub fn read_input(path: &Path, options: &CountOptions) -> Result<Vec<u8>, ProcessError> {
+    let reader = open_input(path, options)?;
+    let mut reader = match &options.range {
+        Some(range) => read_range(reader, range)?,
+        None => reader,
+    };
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// 複数の入力を順に開き、1つのストリームとして読むリーダー
+/// 前の入力を読み終えてから次の入力を開くため、同時に開くのは1つのみ
+struct ConcatReader<'a, F> {
+    paths: std::slice::Iter<'a, PathBuf>,
+    options: &'a CountOptions,
+    current: Option<Box<dyn Read>>,  // 読み込み中の入力
+    on_error: F,  // 開けなかった入力を報告する関数
+    opened: usize,  // 開けた入力の数
+}
+
+impl<F: FnMut(&Path, ProcessError)> Read for ConcatReader<'_, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(reader) = &mut self.current {
+                let n = reader.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+                self.current = None;
+            }
+            let Some(path) = self.paths.next() else {
+                return Ok(0);
+            };
+            match open_input(path, self.options) {
+                Ok(reader) => {
+                    self.current = Some(reader);
+                    self.opened += 1;
+                }
+                Err(e) => (self.on_error)(path, e),
+            }
+        }
+    }
+}
+
+/// 複数の入力を順に連結した1つの文書として統計情報を取得する関数
+/// `cat a b | text-stats -` と同じく、改行で終わらない入力の末尾の単語は次の入力の先頭の単語とつながる
+///
+/// 開けなかった入力は `on_error` で報告して読み飛ばし、ファイル名は連結した入力の数（`3 files`）とする。
+/// ZIPアーカイブは展開せずにそのまま読み、`range` は連結した後の内容に対して適用する。
+pub fn process_concat(
+    paths: &[PathBuf],
+    options: &CountOptions,
+    on_error: impl FnMut(&Path, ProcessError),
+) -> Result<Stats, ProcessError> {
+    let mut reader = ConcatReader {
+        paths: paths.iter(),
+        options,
+        current: None,
+        on_error,
+        opened: 0,
+    };
+    let counts = match &options.range {
+        Some(range) => count_stats_with_options(read_range(Box::new(&mut reader), range)?, options)?,
+        None => count_stats_with_options(&mut reader, options)?,
+    };
+    let filename = match reader.opened {
+        1 => "1 file".to_string(),
+        n => format!("{} files", n),
+    };
+    Ok(Stats { filename, counts })
+}
+
+/// 入力を読み込まずに、処理できるかどうかを確認する関数
+/// `process_input` と同じくファイル・URL・標準入力を区別し、ファイルは存在して開けること、
+/// URLは取得できること、ZIPアーカイブは目次を読めることを確認する（内容は読まない）
+pub fn check_input(path: &Path, options: &CountOptions) -> Result<(), ProcessError> {
+    if path.to_string_lossy() == "-" {
+        return Ok(());
+    }
+    if is_url(path) {
+        with_url(&path.to_string_lossy(), options, |_| Ok(()))?;
+        return Ok(());
+    }
+    if fs::metadata(path)?.is_dir() {
+        return Err(ProcessError::IsDirectory);
+    }
+    let file = File::open(path)?;
+    if options.decompress && is_zip(path) {
+        zip::ZipArchive::new(BufReader::new(file))?;
+    }
+    Ok(())
+}
+
+/// ディレクトリ走査時の設定を保持する構造体
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+    pub follow_symlinks: bool,  // シンボリックリンク先のディレクトリにも入るかどうか
+    pub exclude: Vec<Pattern>,  // 走査から除外するファイル・ディレクトリのパターン
+    pub include_ext: Vec<String>,  // 空でなければ、これらの拡張子のファイルのみを対象にする
+    pub exclude_ext: Vec<String>,  // 対象から除く拡張子
+    pub modified_since: Option<SystemTime>,  // 指定した場合、これより前に更新されたファイルを対象から除く
+}
+
+impl WalkOptions {
+    /// パスが除外パターンのいずれかに一致するかどうかを判定する関数
+    /// ファイル名と、走査の起点からのパス全体のどちらかに一致すれば除外する
+    fn is_excluded(&self, path: &Path) -> bool {
+        let name = path.file_name().map(Path::new).unwrap_or(path);
+        self.exclude
+            .iter()
+            .any(|pattern| pattern.matches_path(name) || pattern.matches_path(path))
+    }
+
+    /// ファイルの更新日時が `modified_since` 以降かどうかを判定する関数
+    /// 更新日時を取得できない場合は対象に含める
+    fn is_recent(&self, metadata: &fs::Metadata) -> bool {
+        self.modified_since
+            .is_none_or(|since| metadata.modified().ok().is_none_or(|modified| modified >= since))
+    }
+
+    /// ファイルの拡張子が `include_ext` `exclude_ext` の指定に合うかどうかを判定する関数
+    /// 大文字小文字は区別せず、指定の先頭の `.` は無視する。`include_ext` を先に適用してから `exclude_ext` で除く。
+    /// 拡張子のないファイルは `include_ext` が空の場合のみ合うものとする
+    fn matches_ext(&self, path: &Path) -> bool {
+        let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+        let listed = |list: &[String]| {
+            ext.as_ref().is_some_and(|ext| {
+                list.iter().any(|listed| listed.trim_start_matches('.').to_lowercase() == *ext)
+            })
+        };
+        (self.include_ext.is_empty() || listed(&self.include_ext)) && !listed(&self.exclude_ext)
+    }
+}
+
+/// ディレクトリを再帰的に走査して、含まれる通常ファイルのパスを集める関数
+///
+/// パスは名前順に並べ、引数のディレクトリを起点とした相対パスで返す。
+/// ファイルへのシンボリックリンクはリンク先を数える。ディレクトリへのリンクは
+/// `follow_symlinks` が有効な場合のみ辿り、ループや二重カウントを避けるため
+/// 訪問済みのディレクトリを正規化したパスで記録して同じディレクトリには二度入らない。
+/// `exclude` に一致するファイルは結果に含めず、一致するディレクトリには入らない。
+/// `include_ext` `exclude_ext` の指定に合わない拡張子のファイルや、`modified_since` より前に更新されたファイルも結果に含めない。
+pub fn walk_dir(dir: &Path, options: &WalkOptions) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    walk_dir_into(dir, options, &mut visited, &mut files)?;
+    Ok(files)
+}
+
+fn walk_dir_into(
+    dir: &Path,
+    options: &WalkOptions,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !visited.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if options.is_excluded(&path) {
+            continue;
+        }
+        let is_symlink = entry.file_type()?.is_symlink();
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {
+                if !is_symlink || options.follow_symlinks {
+                    walk_dir_into(&path, options, visited, files)?;
+                }
+            }
+            Ok(metadata) if !metadata.is_file() => {}  // デバイスファイル等は対象外
+            Ok(metadata) if !options.is_recent(&metadata) => {}
+            // リンク切れなどはファイルとして扱い、処理時にエラーとして報告する
+            _ => {
+                if options.matches_ext(&path) {
+                    files.push(path);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 任意項目のカウントを合計する関数（どちらかが集計されていれば合計する）
+fn sum_option(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    }
+}
+
+impl Stats {
+    /// 別のファイルの統計情報を足し合わせる関数
+    /// 件数は合計し、最長行の文字数などの最大値は大きい方を取る。平均値は合計から計算し直す。
+    /// `filename` は変更しないため、必要に応じて呼び出し側で設定する
+    pub fn merge(&mut self, other: &Stats) {
+        // 行のないファイルの最短行の文字数（0）は比べない
+        if self.counts.lines == 0 {
+            self.counts.min_line_length = other.counts.min_line_length;
+        } else if other.counts.lines > 0 {
+            self.counts.min_line_length =
+                self.counts.min_line_length.min(other.counts.min_line_length);
+        }
+        self.counts.sample_every = self.counts.sample_every.or(other.counts.sample_every);
+        self.counts.extrapolated = self.counts.extrapolated.or(other.counts.extrapolated);
+        // 最長行の行番号は最長行を含むファイルでの行番号とする
+        if other.counts.max_line_length > self.counts.max_line_length || self.counts.lines == 0 {
+            self.counts.max_line_length = other.counts.max_line_length;
+            self.counts.max_line_number = other.counts.max_line_number;
+        }
+        self.counts.lines += other.counts.lines;
+        self.counts.words += other.counts.words;
+        self.counts.chars += other.counts.chars;
+        self.counts.chars_no_spaces += other.counts.chars_no_spaces;
+        self.counts.bytes += other.counts.bytes;
+        self.counts.empty_lines += other.counts.empty_lines;
+        self.counts.blank_lines += other.counts.blank_lines;
+        self.counts.trailing_ws_lines += other.counts.trailing_ws_lines;
+        self.counts.sentences += other.counts.sentences;
+        self.counts.paragraphs += other.counts.paragraphs;
+        self.counts.letters += other.counts.letters;
+        self.counts.digits += other.counts.digits;
+        self.counts.whitespace += other.counts.whitespace;
+        self.counts.punctuation += other.counts.punctuation;
+        self.counts.non_ascii += other.counts.non_ascii;
+        self.counts.graphemes = sum_option(self.counts.graphemes, other.counts.graphemes);
+        self.counts.display_width =
+            sum_option(self.counts.display_width, other.counts.display_width);
+        self.counts.matches = sum_option(self.counts.matches, other.counts.matches);
+        self.counts.matching_lines =
+            sum_option(self.counts.matching_lines, other.counts.matching_lines);
+        self.counts.palindrome_lines =
+            sum_option(self.counts.palindrome_lines, other.counts.palindrome_lines);
+        self.counts.tab_indented = sum_option(self.counts.tab_indented, other.counts.tab_indented);
+        self.counts.space_indented =
+            sum_option(self.counts.space_indented, other.counts.space_indented);
+        self.counts.mixed_indent_lines =
+            sum_option(self.counts.mixed_indent_lines, other.counts.mixed_indent_lines);
+        self.counts.crlf = sum_option(self.counts.crlf, other.counts.crlf);
+        self.counts.lf = sum_option(self.counts.lf, other.counts.lf);
+        self.counts.cr = sum_option(self.counts.cr, other.counts.cr);
+        self.counts.lossy |= other.counts.lossy;
+        self.counts.is_binary |= other.counts.is_binary;
+        self.counts.word_chars += other.counts.word_chars;
+        self.counts.observed_lines += other.counts.observed_lines;
+        self.counts.line_chars += other.counts.line_chars;
+        self.counts.line_lengths.extend(&other.counts.line_lengths);
+        if let Some(word) = &other.counts.longest_word {
+            let longest = self.counts.longest_word.get_or_insert_with(String::new);
+            if word.chars().count() > longest.chars().count() {
+                *longest = word.clone();
+            }
+        }
+        let shortest_word = other.counts.shortest_word.as_ref().filter(|word| !word.is_empty());
+        if let Some(word) = shortest_word {
+            let shortest = self.counts.shortest_word.get_or_insert_with(String::new);
+            if shortest.is_empty() || word.chars().count() < shortest.chars().count() {
+                *shortest = word.clone();
+            }
+        }
+        if other.counts.avg_word_length.is_some() {
+            self.counts.avg_word_length = Some(0.0);  // update_averages で計算する
+        }
+        if let Some(char_counts) = &other.counts.char_counts {
+            let total = self.counts.char_counts.get_or_insert_with(BTreeMap::new);
+            for (&c, count) in char_counts {
+                *total.entry(c).or_insert(0) += count;
+            }
+        }
+        if let Some(word_length_counts) = &other.counts.word_length_counts {
+            let total = self.counts.word_length_counts.get_or_insert_with(BTreeMap::new);
+            for (&len, count) in word_length_counts {
+                *total.entry(len).or_insert(0) += count;
+            }
+        }
+        for (name, value) in &other.counts.metrics {
+            match self.counts.metrics.get_mut(name) {
+                Some(total) => metric::merge_value(total, value),
+                None => {
+                    self.counts.metrics.insert(name.clone(), value.clone());
+                }
+            }
+        }
+        for (word, count) in &other.counts.word_frequencies {
+            *self.counts.word_frequencies.entry(word.clone()).or_insert(0) += count;
+        }
+        // 複数のファイルに現れる単語は1種類と数えるため、合計ではなく和集合の大きさにする
+        if self.counts.unique_words.is_some() || other.counts.unique_words.is_some() {
+            self.counts.unique_words = Some(self.counts.word_frequencies.len());
+        }
+        self.counts.update_averages();
+    }
+}
+
+/// 2つのファイルの統計情報の差（`to` から `from` を引いた値）を保持する構造体
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct StatsDiff {
+    pub from: String,  // 比較元のファイル名
+    pub to: String,    // 比較先のファイル名
+    pub lines: i64,  // 行数の差
+    pub words: i64,  // 単語数の差
+    pub chars: i64,  // 文字数の差
+}
+
+impl StatsDiff {
+    /// `from` から `to` への行数・単語数・文字数の増減を求める関数
+    pub fn new(from: &Stats, to: &Stats) -> Self {
+        let diff = |a: usize, b: usize| b as i64 - a as i64;
+        StatsDiff {
+            from: from.filename.clone(),
+            to: to.filename.clone(),
+            lines: diff(from.counts.lines, to.counts.lines),
+            words: diff(from.counts.words, to.counts.words),
+            chars: diff(from.counts.chars, to.counts.chars),
+        }
+    }
+}
+
+/// 複数ファイルの統計情報を合計する関数
+/// 合計行はファイル名を "total" として返す
+pub fn total_stats(stats: &[Stats]) -> Stats {
+    let mut total = Stats {
+        filename: "total".to_string(),
+        counts: Counts::default(),
+    };
+    for stat in stats {
+        total.merge(stat);
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempfile::NamedTempFile;
+    use std::io::Write;
+
+    #[test]
+    fn test_count_stats() -> io::Result<()> {
+        // 一時ファイルを作成
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all("Hello, World!\nThis is a test.\n".as_bytes())?;
+        
+        let file = File::open(temp_file.path())?;
+        let counts = count_stats(file)?;
+        
+        assert_eq!(counts.lines, 2);
+        assert_eq!(counts.words, 6);  // "Hello,", "World!", "This", "is", "a", "test."
+        assert_eq!(counts.chars, 30); // 13+1+14+1+1=30 (including all newlines)
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_empty() -> io::Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all("".as_bytes())?;
+        
+        let file = File::open(temp_file.path())?;
+        let counts = count_stats(file)?;
+        
+        assert_eq!(counts.lines, 0);
+        assert_eq!(counts.words, 0);
+        assert_eq!(counts.chars, 0);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_builder_matches_count_str() {
+        let inputs = [
+            "Hello, World!\nThis is a test.\n",
+            "text\n\n   \n\t\r\n\r\nmore text\n",
+            "こんにちは 世界\nno newline",
+            "",
+        ];
+        for text in inputs {
+            let options = CountOptions::default();
+            let mut builder = StatsBuilder::new(&options);
+            for line in text.split_inclusive('\n') {
+                builder.push_line(line);
+            }
+            let stats = builder.build("socket".to_string());
+            assert_eq!(stats.filename, "socket");
+            assert_eq!(stats.counts, count_str(text), "input: {text:?}");
+        }
+
+        // 設定を指定した場合も同じ設定で全体を数えた結果と一致する
+        let options = CountOptions {
+            word_lengths: true,
+            unique_words: true,
+            line_endings: true,
+            ..Default::default()
+        };
+        let text = "one two\r\nthree one\n";
+        let mut builder = StatsBuilder::new(&options);
+        builder.push_line("one two\r\n");
+        builder.push_line("three one\n");
+        assert_eq!(
+            builder.build("lines".to_string()).counts,
+            count_bytes_with_options(text.as_bytes(), &options)
+        );
+    }
+
+    #[test]
+    fn test_count_str() -> io::Result<()> {
+        let counts = count_str("Hello, World!\nThis is a test.\n");
+        assert_eq!(counts.lines, 2);
+        assert_eq!(counts.words, 6);
+        assert_eq!(counts.chars, 30);
+
+        let counts = count_str("Line 1\nLine 2\nLine 3\n");
+        assert_eq!((counts.lines, counts.words, counts.chars), (3, 6, 21));
+
+        let counts = count_str("café\n");
+        assert_eq!((counts.chars, counts.bytes), (5, 6));
+
+        let counts = count_str("no newline");
+        assert_eq!((counts.lines, counts.words), (1, 2));
+
+        assert_eq!(count_str(""), Counts::default());
+
+        // 読み込みを経由した場合と全ての項目が一致する
+        let inputs = [
+            "text\n\n   \n\t\r\n\r\nmore text\n",
+            "こんにちは 世界\r\n\nHello, World! e\u{301}\n",
+            "Mr. Smith went home. Did he?\n\nYes!\n",
+        ];
+        for input in inputs {
+            assert_eq!(count_str(input), count_stats(input.as_bytes())?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_count_newlines() -> io::Result<()> {
+        let options = CountOptions {
+            count_newlines: true,
+            ..Default::default()
+        };
+        let lines = |text: &str| -> io::Result<(usize, usize)> {
+            let default = count_stats(text.as_bytes())?.lines;
+            let newlines = count_stats_with_options(text.as_bytes(), &options)?.lines;
+            Ok((default, newlines))
+        };
+        // デフォルトでは末尾に改行のない最終行も1行と数え、`wc -l` は改行の数を数える
+        assert_eq!(lines("a\nb")?, (2, 1));
+        assert_eq!(lines("a\nb\n")?, (2, 2));
+        assert_eq!(lines("a\nb\r\n")?, (2, 2));
+        assert_eq!(lines("no newline")?, (1, 0));
+        assert_eq!(lines("\n")?, (1, 1));
+        assert_eq!(lines("")?, (0, 0));
+
+        // 行数以外の項目は変わらない
+        let counts = count_stats_with_options("a b\nc".as_bytes(), &options)?;
+        assert_eq!((counts.words, counts.chars, counts.bytes), (3, 5, 5));
+
+        // 平均は区切りのない最終行も含めて実際に集計した行から計算する
+        let counts = count_stats_with_options("a\nbbb".as_bytes(), &options)?;
+        assert_eq!(counts.lines, 1);
+        assert_eq!(counts.avg_line_length, 2.0);
+        assert!(counts.avg_line_length <= counts.max_line_length as f64);
+        assert_eq!(counts.avg_words_per_line, 1.0);
+        let total = total_stats(&[
+            Stats { filename: "a".to_string(), counts },
+            Stats {
+                filename: "b".to_string(),
+                counts: count_stats_with_options("a\nbbb".as_bytes(), &options)?,
+            },
+        ]);
+        assert_eq!(total.counts.avg_line_length, 2.0);
+
+        // 区切りを指定した場合は区切りの数を数える
+        let options = CountOptions {
+            count_newlines: true,
+            delimiter: Some(b','),
+            ..Default::default()
+        };
+        assert_eq!(count_stats_with_options("a,b,c".as_bytes(), &options)?.lines, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_multiple_lines() -> io::Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all("Line 1\nLine 2\nLine 3\n".as_bytes())?;
+        
+        let file = File::open(temp_file.path())?;
+        let counts = count_stats(file)?;
+        
+        assert_eq!(counts.lines, 3);
+        assert_eq!(counts.words, 6);  // "Line", "1", "Line", "2", "Line", "3"
+        assert_eq!(counts.chars, 21); // 6+6+6+3(\n) = 21
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_whitespace_matches_separate_passes() -> io::Result<()> {
+        let inputs = [
+            "  leading spaces\n",
+            "trailing spaces   \n",
+            "multiple    spaces\tand\t\ttabs\n",
+            "   \n\n \t \n",
+            "no trailing newline   ",
+            "crlf  line\r\n  end.  Next!\r\n",
+            "全角\u{3000}空白\u{a0}と　改行\u{2028}区切り。次の文。\n",
+            "",
+        ];
+        for input in inputs {
+            // 行・単語・文字・バイトを別々に数えた結果と一致する
+            let counts = count_stats(input.as_bytes())?;
+            assert_eq!(counts.lines, input.split_inclusive('\n').count(), "{input:?}");
+            assert_eq!(counts.words, input.split_whitespace().count(), "{input:?}");
+            assert_eq!(counts.chars, input.chars().count(), "{input:?}");
+            assert_eq!(counts.bytes, input.len(), "{input:?}");
+            let sentences: usize = input.split_whitespace().map(sentence_ends).sum();
+            assert_eq!(counts.sentences, sentences, "{input:?}");
+
+            // 単語を取り出して数える場合とも一致する
+            let options = CountOptions {
+                word_lengths: true,
+                ..Default::default()
+            };
+            let words = count_stats_with_options(input.as_bytes(), &options)?.words;
+            assert_eq!(words, counts.words, "{input:?}");
+        }
+
+        // 区切りは空白でなくても単語の区切りになる
+        let options = CountOptions {
+            delimiter: Some(b'\0'),
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("one two\0three\0".as_bytes(), &options)?;
+        assert_eq!(counts.words, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_bytes_differ_from_chars() -> io::Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all("café\n".as_bytes())?;
+
+        let file = File::open(temp_file.path())?;
+        let counts = count_stats(file)?;
+
+        assert_eq!(counts.chars, 5); // "é" は1文字
+        assert_eq!(counts.bytes, 6); // "é" はUTF-8で2バイト
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_stats() -> io::Result<()> {
+        let mut first = NamedTempFile::new()?;
+        first.write_all("Hello, World!\n".as_bytes())?;
+        let mut second = NamedTempFile::new()?;
+        second.write_all("Line 1\nLine 2\n".as_bytes())?;
+
+        let stats = vec![
+            process_file(first.path(), &CountOptions::default())?,
+            process_file(second.path(), &CountOptions::default())?,
+        ];
+        let total = total_stats(&stats);
+
+        assert_eq!(total.filename, "total");
+        assert_eq!(total.counts.lines, 1 + 2);
+        assert_eq!(total.counts.words, 2 + 4);
+        assert_eq!(total.counts.chars, 14 + 14);
+        assert_eq!(total.counts.bytes, 14 + 14);
+        assert_eq!(total.counts.avg_words_per_line, 6.0 / 3.0); // 合計値から計算し直す
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_merge_sums_counts() -> io::Result<()> {
+        let mut merged = Stats {
+            filename: "merged".to_string(),
+            counts: count_stats("one two\n\nthree\n".as_bytes())?,
+        };
+        let other = Stats {
+            filename: "other".to_string(),
+            counts: count_stats("four five six\n".as_bytes())?,
+        };
+        merged.merge(&other);
+
+        assert_eq!(merged.filename, "merged"); // ファイル名は変更しない
+        assert_eq!(merged.counts.lines, 3 + 1);
+        assert_eq!(merged.counts.words, 3 + 3);
+        assert_eq!(merged.counts.bytes, 15 + 14);
+        assert_eq!(merged.counts.empty_lines, 1);
+        assert_eq!(merged.counts.paragraphs, 2 + 1);
+        assert_eq!(merged.counts.avg_words_per_line, 6.0 / 4.0);
+        // 集計していない任意項目は None のまま
+        assert_eq!(merged.counts.graphemes, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_merge_takes_maximum() -> io::Result<()> {
+        let options = CountOptions {
+            word_lengths: true,
+            ..Default::default()
+        };
+        let mut merged = Stats {
+            filename: "total".to_string(),
+            counts: count_stats_with_options("a much longer line\n".as_bytes(), &options)?,
+        };
+        let other = Stats {
+            filename: "other".to_string(),
+            counts: count_stats_with_options("tiny\nextraordinary\n".as_bytes(), &options)?,
+        };
+        merged.merge(&other);
+
+        assert_eq!(merged.counts.max_line_length, 18);
+        assert_eq!(merged.counts.longest_word.as_deref(), Some("extraordinary"));
+        assert_eq!(merged.counts.shortest_word.as_deref(), Some("a"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_no_trailing_newline() -> io::Result<()> {
+        let counts = count_stats("Line 1\nLine 2".as_bytes())?;
+
+        assert_eq!(counts.lines, 2); // 改行のない最終行もカウントする
+        assert_eq!(counts.words, 4);
+        assert_eq!(counts.chars, 13);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_buffer_size() -> io::Result<()> {
+        // バッファより長い行や、バッファの境界をまたぐ文字を含む入力
+        let input = "short\n".to_string() + &"長い行 long line ".repeat(50) + "\nend";
+        let with_size = |size| -> io::Result<Counts> {
+            let options = CountOptions {
+                buffer_size: NonZeroUsize::new(size),
+                ..Default::default()
+            };
+            count_stats_with_options(input.as_bytes(), &options)
+        };
+        let expected = count_stats(input.as_bytes())?;
+        assert_eq!(with_size(1)?, expected);
+        assert_eq!(with_size(7)?, expected);
+        assert_eq!(with_size(1 << 20)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_word_across_read_boundary() -> io::Result<()> {
+        // "Hello" が2回の読み込みに分割されても1単語として数える
+        let reader = "Hel".as_bytes().chain("lo World\n".as_bytes());
+        let counts = count_stats(reader)?;
+
+        assert_eq!(counts.lines, 1);
+        assert_eq!(counts.words, 2);
+        assert_eq!(counts.chars, 12);
+
+        Ok(())
+    }
+
+    /// 同じ行を繰り返す終わりのないストリーム（`yes` と同様）
+    /// 読み込まれた分だけを生成するため、内容全体をメモリに持たない
+    struct Yes {
+        line: &'static [u8],
+        pos: usize,  // 次に渡す行内の位置
+        max_read: usize,  // 1回の読み込みで要求された最大のバイト数
+    }
+
+    impl Yes {
+        fn new(line: &'static [u8]) -> Self {
+            Yes { line, pos: 0, max_read: 0 }
+        }
+    }
+
+    impl Read for Yes {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.max_read = self.max_read.max(buf.len());
+            // 要求された分だけ行を繰り返して埋める
+            for byte in buf.iter_mut() {
+                *byte = self.line[self.pos];
+                self.pos = (self.pos + 1) % self.line.len();
+            }
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_count_stats_unbounded_stream() -> io::Result<()> {
+        // 不正なUTF-8を含む行を繰り返すストリームの先頭 `LINES` 行のみを読む（`yes | head` と同様）
+        // 入力全体をまとめて読み込むと読み込みの要求が大きくなるため、要求が読み込み用のバッファの
+        // 大きさを超えないことで、入力より小さいバッファを使って少しずつ読んでいることを確かめる
+        const LINE: &[u8] = b"y yes \xff\xfe ok\n";
+        const LINES: usize = 500_000;
+        let mut yes = Yes::new(LINE);
+        let counts = count_stats((&mut yes).take((LINE.len() * LINES) as u64))?;
+        assert!(LINE.len() * LINES > DEFAULT_BUFFER_SIZE);
+        assert!(yes.max_read <= DEFAULT_BUFFER_SIZE);
+
+        assert_eq!(counts.lines, LINES);
+        assert_eq!(counts.words, 4 * LINES);
+        assert_eq!(counts.bytes, LINE.len() * LINES);
+        // 不正なバイトがあっても中断せず、1バイトずつ置換文字として数え続ける
+        assert!(counts.lossy);
+        assert_eq!(counts.chars, 12 * LINES);
+
+        // 文字コードの指定に従って行ごとに変換する
+        let options = CountOptions {
+            encoding: Encoding::Latin1,
+            ..Default::default()
+        };
+        let stream = Yes::new(LINE).take((LINE.len() * 1000) as u64);
+        let counts = count_stats_with_options(stream, &options)?;
+        assert!(!counts.lossy);
+        // Latin-1では1バイトが1文字になる
+        assert_eq!((counts.lines, counts.chars), (1000, LINE.len() * 1000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_max_line_length() -> io::Result<()> {
+        let counts = count_stats("short\r\nthe longest\nmid\n".as_bytes())?;
+        assert_eq!(counts.max_line_length, 11); // 改行は含めない
+
+        // 改行のない最終行も測定する
+        let counts = count_stats("a\nthe longest".as_bytes())?;
+        assert_eq!(counts.max_line_length, 11);
+
+        let counts = count_stats("".as_bytes())?;
+        assert_eq!(counts.max_line_length, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_max_line_number() -> io::Result<()> {
+        let counts = count_stats("short\nmedium\nthe longest\ntiny\nthe longest\n".as_bytes())?;
+        assert_eq!(counts.max_line_length, 11);
+        assert_eq!(counts.max_line_number, 3); // 同じ長さの場合は先の行
+
+        // 絞り込んだ場合は一致した行の中での行番号を返す
+        let options = CountOptions {
+            line_filter: Some(Regex::new("^t").unwrap()),
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("a b c d\nto\nthe end\n".as_bytes(), &options)?;
+        assert_eq!((counts.max_line_length, counts.max_line_number), (7, 2));
+
+        assert_eq!(count_stats("\n\n".as_bytes())?.max_line_number, 1);
+        assert_eq!(count_stats("".as_bytes())?.max_line_number, 0);
+
+        // 合計では最長行を含むファイルでの行番号になる
+        let stat = |text: &str| -> io::Result<Stats> {
+            let counts = count_stats(text.as_bytes())?;
+            Ok(Stats { filename: String::new(), counts })
+        };
+        let total = total_stats(&[stat("abc\n")?, stat("a\nabcdef\n")?, stat("abcdef\n")?]);
+        assert_eq!((total.counts.max_line_length, total.counts.max_line_number), (6, 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_line_stats() -> io::Result<()> {
+        let counts = count_stats("abcde\nabc\nabcdefg\n".as_bytes())?;
+        assert_eq!(counts.min_line_length, 3);
+        assert_eq!(counts.max_line_length, 7);
+        assert_eq!(counts.avg_line_length, 5.0);
+
+        // 空のファイルはすべて0
+        let counts = count_stats("".as_bytes())?;
+        assert_eq!((counts.min_line_length, counts.max_line_length), (0, 0));
+        assert_eq!(counts.avg_line_length, 0.0);
+
+        // 合計では行のないファイルを除いた最短の行になる
+        let stat = |text: &str| -> io::Result<Stats> {
+            let counts = count_stats(text.as_bytes())?;
+            Ok(Stats { filename: String::new(), counts })
+        };
+        let total = total_stats(&[stat("")?, stat("abcd\nabcdef\n")?, stat("ab\n")?]);
+        assert_eq!(total.counts.min_line_length, 2);
+        assert_eq!(total.counts.max_line_length, 6);
+        assert_eq!(total.counts.avg_line_length, 4.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_tab_width() -> io::Result<()> {
+        let text = "\tab\nx\ty\n";
+        // デフォルトではコマンドラインと同じく8列ごとのタブ位置まで展開する
+        assert_eq!(count_stats(text.as_bytes())?.max_line_length, 10);
+        assert_eq!(count_str(text).max_line_length, 10);
+
+        // 0 の場合はタブも1文字として数える
+        let options = CountOptions {
+            tab_width: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(count_stats_with_options(text.as_bytes(), &options)?.max_line_length, 3);
+
+        let options = CountOptions {
+            tab_width: Some(4),
+            display_width: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.max_line_length, 6); // 行頭のタブは4列
+        assert_eq!(counts.display_width, Some(6 + 5)); // "x" の後のタブは3列
+        assert_eq!(counts.chars, 8); // 文字数には影響しない
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_empty_and_blank_lines() -> io::Result<()> {
+        let counts = count_stats("text\n\n   \n\t\r\n\r\nmore text\n".as_bytes())?;
+
+        assert_eq!(counts.lines, 6);
+        assert_eq!(counts.empty_lines, 2); // "\n" と "\r\n"
+        assert_eq!(counts.blank_lines, 2); // "   " と "\t"
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_trailing_whitespace() -> io::Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(b"clean\nspace \ntab\t\r\n  indented\n\nmixed \t\n   \nlast  ")?;
+
+        // 改行の前の空白とタブ、空白のみの行、改行のない最終行の末尾の空白を数える
+        let counts = count_stats(File::open(temp_file.path())?)?;
+        assert_eq!(counts.lines, 8);
+        assert_eq!(counts.trailing_ws_lines, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_char_classes() -> io::Result<()> {
+        let counts = count_stats("ab 12!\n".as_bytes())?;
+        assert_eq!(counts.letters, 2);
+        assert_eq!(counts.digits, 2);
+        assert_eq!(counts.whitespace, 2); // " " と "\n"
+        assert_eq!(counts.punctuation, 1);
+
+        let counts = count_stats("日本語、テスト。\tcafé ①\n".as_bytes())?;
+        assert_eq!(
+            counts.letters + counts.digits + counts.whitespace + counts.punctuation,
+            counts.chars
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_averages() -> io::Result<()> {
+        let counts = count_stats("one two\nthree four five six\n".as_bytes())?;
+        assert_eq!(counts.avg_words_per_line, 3.0);
+        assert_eq!(counts.avg_chars_per_word, 28.0 / 6.0);
+
+        // 分母が0の場合は0.0
+        let counts = count_stats("".as_bytes())?;
+        assert_eq!(counts.avg_words_per_line, 0.0);
+        assert_eq!(counts.avg_chars_per_word, 0.0);
+
+        let counts = count_stats("   \n\n".as_bytes())?;
+        assert_eq!(counts.avg_words_per_line, 0.0);
+        assert_eq!(counts.avg_chars_per_word, 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_word_lengths() -> io::Result<()> {
+        let options = CountOptions {
+            word_lengths: true,
+            ..Default::default()
+        };
+        let text = "I like big elephants\nand tiny ants\n";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.longest_word.as_deref(), Some("elephants"));
+        assert_eq!(counts.shortest_word.as_deref(), Some("I"));
+        assert_eq!(counts.avg_word_length, Some(28.0 / 7.0));
+
+        // 同じ長さの場合は先に出現した単語
+        let counts = count_stats_with_options("ab cd ef\n".as_bytes(), &options)?;
+        assert_eq!(counts.longest_word.as_deref(), Some("ab"));
+        assert_eq!(counts.shortest_word.as_deref(), Some("ab"));
+
+        let counts = count_stats_with_options("".as_bytes(), &options)?;
+        assert_eq!(counts.longest_word.as_deref(), Some(""));
+        assert_eq!(counts.avg_word_length, Some(0.0));
+
+        assert_eq!(count_stats("ab cd\n".as_bytes())?.longest_word, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_word_pattern() -> io::Result<()> {
+        let text = "hello-world foo,bar 42 baz.\n";
+
+        // デフォルトでは空白で区切る
+        let counts = count_stats(text.as_bytes())?;
+        assert_eq!(counts.words, 4);
+
+        // アルファベットの連続のみを単語とみなす
+        let options = CountOptions {
+            word_pattern: Some(Regex::new("[A-Za-z]+").unwrap()),
+            word_frequencies: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.words, 5);
+        assert_eq!(counts.word_frequencies.get("world"), Some(&1));
+        assert_eq!(counts.word_frequencies.get("42"), None);
+        // 文の数は単語の定義に影響されない
+        assert_eq!(counts.sentences, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_line_filter() -> io::Result<()> {
+        let text = "ERROR disk full\nINFO started\nERROR out of memory now\nDEBUG tick\n";
+        let options = CountOptions {
+            line_filter: Some(Regex::new("^ERROR").unwrap()),
+            ..Default::default()
+        };
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.lines, 2);
+        assert_eq!(counts.words, 8);
+        assert_eq!(counts, count_stats("ERROR disk full\nERROR out of memory now\n".as_bytes())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_chars_no_spaces() -> io::Result<()> {
+        let counts = count_stats("a b c\n".as_bytes())?;
+        assert_eq!(counts.chars, 6);
+        assert_eq!(counts.chars_no_spaces, 3);
+
+        // 全角スペースやタブも空白として除く
+        assert_eq!(count_stats("日本\u{3000}語\tだ\r\n".as_bytes())?.chars_no_spaces, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_unique_words() -> io::Result<()> {
+        let options = CountOptions {
+            unique_words: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("the cat the dog\n".as_bytes(), &options)?;
+        assert_eq!(counts.words, 4);
+        assert_eq!(counts.unique_words, Some(3));
+
+        // --top-words と同じく大文字小文字と前後の記号を区別しない
+        let counts = count_stats_with_options("The cat, the \"CAT\".\n".as_bytes(), &options)?;
+        assert_eq!(counts.unique_words, Some(2));
+
+        // 合計では両方のファイルに現れる単語を1種類と数える
+        let first = Stats {
+            filename: "a".to_string(),
+            counts: count_stats_with_options("the cat\n".as_bytes(), &options)?,
+        };
+        let second = Stats {
+            filename: "b".to_string(),
+            counts: count_stats_with_options("the dog\n".as_bytes(), &options)?,
+        };
+        assert_eq!(total_stats(&[first, second]).counts.unique_words, Some(3));
+
+        assert_eq!(count_stats("the cat\n".as_bytes())?.unique_words, None);
+
+        // 大文字小文字を区別する場合は別の単語として数える
+        let options = CountOptions {
+            unique_words: true,
+            case_sensitive: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("The cat, the \"CAT\".\n".as_bytes(), &options)?;
+        assert_eq!(counts.unique_words, Some(4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_delimiter() -> io::Result<()> {
+        let options = CountOptions {
+            delimiter: Some(b'\0'),
+            ..Default::default()
+        };
+        let text = b"first record\0second\nrecord here\0\0last";
+        let counts = count_stats_with_options(&text[..], &options)?;
+        assert_eq!(counts.lines, 4);
+        assert_eq!(counts.words, 6);
+        assert_eq!(counts.empty_lines, 1);
+        // 改行は区切りではないため行の途中の文字として数える
+        assert_eq!(counts.max_line_length, "second\nrecord here".chars().count());
+
+        // メモリマップした場合と同じ結果になる
+        assert_eq!(count_bytes_with_options(text, &options), counts);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_min_word_length() -> io::Result<()> {
+        let options = CountOptions {
+            min_word_length: 3,
+            word_frequencies: true,
+            unique_words: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("a an the apple\n".as_bytes(), &options)?;
+        assert_eq!(counts.words, 2);
+        assert_eq!(counts.unique_words, Some(2));
+        assert_eq!(
+            top_words(&counts.word_frequencies, 10),
+            vec![("apple".to_string(), 1), ("the".to_string(), 1)]
+        );
+        // 長さは文字数で数える
+        let counts = count_stats_with_options("日本 日本語\n".as_bytes(), &options)?;
+        assert_eq!(counts.words, 1);
+        // 1文字以上（デフォルト）の場合はすべての単語を数える
+        let options = CountOptions {
+            min_word_length: 1,
+            ..Default::default()
+        };
+        assert_eq!(count_stats_with_options("a an the apple\n".as_bytes(), &options)?.words, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_word_length_counts() -> io::Result<()> {
+        let options = CountOptions {
+            word_length_dist: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("a bb ccc bb\n".as_bytes(), &options)?;
+        let expected = BTreeMap::from([(1, 1), (2, 2), (3, 1)]);
+        assert_eq!(counts.word_length_counts, Some(expected));
+
+        // 文字数はバイト数ではなく文字で数え、合計ではファイルごとの数を足し合わせる
+        let other = count_stats_with_options("日本 語\n".as_bytes(), &options)?;
+        assert_eq!(other.word_length_counts, Some(BTreeMap::from([(1, 1), (2, 1)])));
+        let total = total_stats(&[
+            Stats { filename: "a.txt".to_string(), counts },
+            Stats { filename: "b.txt".to_string(), counts: other },
+        ]);
+        let expected = BTreeMap::from([(1, 2), (2, 3), (3, 1)]);
+        assert_eq!(total.counts.word_length_counts, Some(expected));
+        // 指定しない場合は数えない
+        assert_eq!(count_stats("a bb\n".as_bytes())?.word_length_counts, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_every_nth() -> io::Result<()> {
+        // i行目は i 個の単語からなる10行（改行を含めて 2i 文字）
+        let text: String = (1..=10).map(|i| vec!["w"; i].join(" ") + "\n").collect();
+        let sampled = |extrapolate| -> io::Result<Counts> {
+            let options = CountOptions {
+                every_nth: NonZeroUsize::new(2),
+                extrapolate,
+                ..Default::default()
+            };
+            count_stats_with_options(text.as_bytes(), &options)
+        };
+
+        // 1, 3, 5, 7, 9行目のみを数える
+        let counts = sampled(false)?;
+        assert_eq!((counts.lines, counts.words, counts.chars), (5, 25, 50));
+        assert_eq!((counts.sample_every, counts.extrapolated), (Some(2), Some(false)));
+        assert_eq!(counts.max_line_length, 17);
+
+        // 推定する場合は件数をN倍し、最長行の文字数や平均はそのまま
+        let estimated = sampled(true)?;
+        assert_eq!((estimated.lines, estimated.words, estimated.chars), (10, 50, 100));
+        assert_eq!(estimated.bytes, counts.bytes * 2);
+        assert_eq!(estimated.extrapolated, Some(true));
+        assert_eq!(estimated.max_line_length, counts.max_line_length);
+        assert_eq!(estimated.avg_words_per_line, counts.avg_words_per_line);
+        assert_eq!(estimated.avg_line_length, counts.avg_line_length);
+
+        // 指定しない場合は標本であることを出力しない
+        let counts = count_stats(text.as_bytes())?;
+        assert_eq!((counts.sample_every, counts.extrapolated), (None, None));
+        assert_eq!(counts.lines, 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_char_counts() -> io::Result<()> {
+        let options = CountOptions {
+            count_chars: vec![',', '\t', ';'],
+            ..Default::default()
+        };
+        let text = "name,age\tcity\nalice,30\ttokyo\nbob,25,\t\n";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        let expected = BTreeMap::from([(',', 4), ('\t', 3), (';', 0)]);
+        assert_eq!(counts.char_counts, Some(expected));
+
+        // 合計ではファイルごとの回数を足し合わせる
+        let other = count_stats_with_options("x,y\n".as_bytes(), &options)?;
+        let total = total_stats(&[
+            Stats { filename: "a.csv".to_string(), counts },
+            Stats { filename: "b.csv".to_string(), counts: other },
+        ]);
+        let expected = BTreeMap::from([(',', 5), ('\t', 3), (';', 0)]);
+        assert_eq!(total.counts.char_counts, Some(expected));
+        // 指定しない場合は数えない
+        assert_eq!(count_stats(text.as_bytes())?.char_counts, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_indentation() -> io::Result<()> {
+        let options = CountOptions {
+            indentation: true,
+            ..Default::default()
+        };
+        let text =
+            "fn main() {\n\tlet a = 1;\n    let b = 2;\n\t\tif a {\n\t    b\n  \t}\n\t\n}\n";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.tab_indented, Some(2));
+        assert_eq!(counts.space_indented, Some(1));
+        // 空白のみの行（`\t\n`）は数えない
+        assert_eq!(counts.mixed_indent_lines, Some(2));
+        assert!(counts.mixes_indentation());
+
+        // 1種類のみで字下げしたファイルは混在とみなさない
+        let counts = count_stats_with_options("a\n\tb\n\t\tc\n".as_bytes(), &options)?;
+        assert_eq!((counts.tab_indented, counts.space_indented), (Some(2), Some(0)));
+        assert!(!counts.mixes_indentation());
+
+        assert_eq!(count_stats(text.as_bytes())?.tab_indented, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_palindromes() -> io::Result<()> {
+        let options = CountOptions {
+            palindromes: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("level\nhello\n\n".as_bytes(), &options)?;
+        assert_eq!(counts.palindrome_lines, Some(1));
+        // 大文字小文字や空白も区別して比べる
+        let counts = count_stats_with_options("Level\nたけやぶやけた\r\n".as_bytes(), &options)?;
+        assert_eq!(counts.palindrome_lines, Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_reversed() -> io::Result<()> {
+        let options = CountOptions {
+            reversed: true,
+            ..Default::default()
+        };
+        let text = "Hello world.\r\nlevel\n";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        let forward = count_stats(text.as_bytes())?;
+        // 行・単語・文字・バイトの数は変わらない
+        assert_eq!(
+            (counts.lines, counts.words, counts.chars, counts.bytes),
+            (forward.lines, forward.words, forward.chars, forward.bytes)
+        );
+        assert_eq!(counts.max_line_length, forward.max_line_length);
+        // 文末の記号が行頭に移るため文として数えない
+        assert_eq!((forward.sentences, counts.sentences), (1, 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_is_binary() -> io::Result<()> {
+        assert!(!count_stats("plain text\n".as_bytes())?.is_binary);
+        assert!(count_stats(&b"\x7fELF\x02\x01\x01\0\0\0"[..])?.is_binary);
+
+        // 先頭 BINARY_SNIFF_LEN バイトより後のNULでは判定しない
+        let mut bytes = vec![b'a'; BINARY_SNIFF_LEN];
+        bytes.push(0);
+        assert!(!count_stats(&bytes[..])?.is_binary);
+        bytes.insert(BINARY_SNIFF_LEN - 1, 0);
+        assert!(count_stats(&bytes[..])?.is_binary);
+
+        // 絞り込みで読み飛ばした行のNULでも判定する
+        let options = CountOptions {
+            line_filter: Some(Regex::new("text").unwrap()),
+            ..Default::default()
+        };
+        assert!(count_stats_with_options(&b"\0\0\ntext\n"[..], &options)?.is_binary);
+        // NULを区切りにした場合はバイナリとみなさない
+        let options = CountOptions {
+            delimiter: Some(b'\0'),
+            ..Default::default()
+        };
+        assert!(!count_stats_with_options(&b"a\0b\0"[..], &options)?.is_binary);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_normalize() -> io::Result<()> {
+        let nfc = "caf\u{e9}\n";
+        let nfd = "cafe\u{301}\n";
+        // 正規化しない場合は合成済みの文字と結合文字で文字数が異なる
+        assert_eq!(count_stats(nfc.as_bytes())?.chars, 5);
+        assert_eq!(count_stats(nfd.as_bytes())?.chars, 6);
+
+        let normalized = |form, text: &str| -> io::Result<Counts> {
+            let options = CountOptions {
+                normalize: Some(form),
+                ..Default::default()
+            };
+            count_stats_with_options(text.as_bytes(), &options)
+        };
+        // NFCに正規化すると同じ文字数になる。バイト数は入力のまま数える
+        let counts = normalized(Normalization::Nfc, nfd)?;
+        assert_eq!(counts.chars, 5);
+        assert_eq!(counts.bytes, nfd.len());
+        assert_eq!(normalized(Normalization::Nfd, nfc)?.chars, 6);
+        // 互換分解では合字も分解する
+        assert_eq!(normalized(Normalization::Nfkc, "\u{fb01}\n")?.chars, 3);
+        assert_eq!(normalized(Normalization::Nfc, "\u{fb01}\n")?.chars, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_strip_ansi() -> io::Result<()> {
+        let options = CountOptions {
+            strip_ansi: true,
+            ..Default::default()
+        };
+        // 指定しない場合はエスケープシーケンスも文字として数える
+        let red = "\x1b[31mred\x1b[0m";
+        assert_eq!(count_stats(red.as_bytes())?.chars, 12);
+        let counts = count_stats_with_options(red.as_bytes(), &options)?;
+        assert_eq!((counts.chars, counts.words), (3, 1));
+        // バイト数は取り除く前の入力のまま数える
+        assert_eq!(counts.bytes, red.len());
+
+        let input = "\x1b[1;32mbold\x1b[m \x1b[2Kgreen\x1b[0m text\n";
+        let counts = count_stats_with_options(input.as_bytes(), &options)?;
+        let plain = count_stats("bold green text\n".as_bytes())?;
+        assert_eq!((counts.chars, counts.words), (plain.chars, plain.words));
+        assert_eq!(counts.max_line_length, plain.max_line_length);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_hash() -> io::Result<()> {
+        let hash = |algorithm, text: &str| -> io::Result<Option<String>> {
+            let options = CountOptions {
+                hash: Some(algorithm),
+                ..Default::default()
+            };
+            Ok(count_stats_with_options(text.as_bytes(), &options)?.hash)
+        };
+        // `sha256sum` などで求めた値と一致する
+        assert_eq!(
+            hash(HashAlgorithm::Sha256, "hello\n")?.as_deref(),
+            Some("5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03")
+        );
+        assert_eq!(
+            hash(HashAlgorithm::Md5, "hello\n")?.as_deref(),
+            Some("b1946ac92492d2347c6235b4d2611184")
+        );
+        assert_eq!(
+            hash(HashAlgorithm::Sha1, "hello\n")?.as_deref(),
+            Some("f572d396fae9206628714fb2ce00f72e94f2258f")
+        );
+        // 絞り込みで読み飛ばした行も含めたファイル全体から求める
+        let options = CountOptions {
+            hash: Some(HashAlgorithm::Sha256),
+            line_filter: Some(Regex::new("world").unwrap()),
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("hello\nworld\n".as_bytes(), &options)?;
+        assert_eq!(counts.lines, 1);
+        assert_eq!(
+            counts.hash.as_deref(),
+            Some("4a1e67f2fe1d1cc7b31d0ca2ec441da4778203a036a77da10344c85e24ff0f92")
+        );
+        // 指定しない場合は求めない
+        assert_eq!(count_stats("hello\n".as_bytes())?.hash, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_non_ascii() -> io::Result<()> {
+        assert_eq!(count_stats("plain ascii text\n".as_bytes())?.non_ascii, 0);
+        assert_eq!(count_stats("café au lait\n".as_bytes())?.non_ascii, 1);
+        // 不正なバイト列もASCIIではない
+        assert_eq!(count_stats(&b"caf\xe9\n"[..])?.non_ascii, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_line_endings() -> io::Result<()> {
+        let options = CountOptions {
+            line_endings: true,
+            ..Default::default()
+        };
+        let text = "windows\r\nunix\nold mac\rmixed\r\r\nlast\r";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.crlf, Some(2));
+        assert_eq!(counts.lf, Some(1));
+        assert_eq!(counts.cr, Some(3));
+        assert_eq!(counts.lines, 4);
+
+        let counts = count_stats_with_options("".as_bytes(), &options)?;
+        assert_eq!((counts.crlf, counts.lf, counts.cr), (Some(0), Some(0), Some(0)));
+
+        assert_eq!(count_stats("a\r\n".as_bytes())?.crlf, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_line_offsets() -> io::Result<()> {
+        let options = CountOptions {
+            positions: true,
+            ..Default::default()
+        };
+        let text = "first\r\n\nこんにちは\nlast";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        // 各行の先頭は直前までの行のバイト数の累計（「こんにちは」は15バイト）
+        let mut expected = Vec::new();
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            expected.push(offset);
+            offset += line.len();
+        }
+        assert_eq!(expected, vec![0, 7, 8, 24]);
+        assert_eq!(counts.line_offsets, Some(expected));
+
+        // 絞り込んだ場合は数えた行の位置のみを記録する
+        let options = CountOptions {
+            positions: true,
+            line_filter: Some(Regex::new("t$").unwrap()),
+            ..Default::default()
+        };
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.line_offsets, Some(vec![0, 24]));
+
+        assert_eq!(count_stats(text.as_bytes())?.line_offsets, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_sentences() -> io::Result<()> {
+        let text = "Hello world. How are you?! I'm fine...\n\
+                    Mr. Smith uses e.g. and i.e. often. \"Really?\" she said\n\
+                    Pi is 3.14 and it ends here\n";
+        let counts = count_stats(text.as_bytes())?;
+        // "world." "you?!" "fine..." "often." "Really?\"" の5つ（最終行は文末記号がない）
+        assert_eq!(counts.sentences, 5);
+
+        let counts = count_stats("これは文です。次の文です！本当？！\n".as_bytes())?;
+        assert_eq!(counts.sentences, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_paragraphs() -> io::Result<()> {
+        let text = "\nFirst paragraph\nstill first\n\n  \nSecond\n\nThird\n\n \t\n";
+        let counts = count_stats(text.as_bytes())?;
+        // 先頭・末尾の空行で空の段落はできない
+        assert_eq!(counts.paragraphs, 3);
+
+        assert_eq!(count_stats("".as_bytes())?.paragraphs, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_length_histogram() -> io::Result<()> {
+        assert_eq!(line_length_histogram(&[0, 5, 10, 11, 20, 21, 35], 10), vec![3, 2, 1, 1]);
+        // 途中の空の範囲も0として含める
+        assert_eq!(line_length_histogram(&[1, 25], 10), vec![1, 0, 1]);
+        assert!(line_length_histogram(&[], 10).is_empty());
+
+        let options = CountOptions {
+            line_lengths: true,
+            ..Default::default()
+        };
+        let text = format!("{}\n\n{}\n{}", "a".repeat(3), "b".repeat(12), "c".repeat(10));
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.line_lengths, vec![3, 0, 12, 10]);
+        assert_eq!(line_length_histogram(&counts.line_lengths, 5), vec![2, 1, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_words() -> io::Result<()> {
+        let options = CountOptions {
+            word_frequencies: true,
+            ..Default::default()
+        };
+        let text = "Hello, World! hello world.\nThe world is big; the end.\n";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+
+        assert_eq!(
+            top_words(&counts.word_frequencies, 3),
+            vec![
+                ("world".to_string(), 3),
+                ("hello".to_string(), 2),
+                ("the".to_string(), 2), // 同数の場合はアルファベット順
+            ]
+        );
+        // デフォルトでは集計しない
+        assert!(count_stats(text.as_bytes())?.word_frequencies.is_empty());
+
+        let options = CountOptions {
+            word_frequencies: true,
+            case_sensitive: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(
+            top_words(&counts.word_frequencies, 2),
+            vec![("world".to_string(), 2), ("Hello".to_string(), 1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_graphemes() -> io::Result<()> {
+        let options = CountOptions {
+            graphemes: true,
+            ..Default::default()
+        };
+        // "e" + 結合用アキュートアクセント
+        let counts = count_stats_with_options("e\u{301}\r\n".as_bytes(), &options)?;
+
+        assert_eq!(counts.chars, 4);
+        assert_eq!(counts.graphemes, Some(2)); // "é" と "\r\n"
+        assert_eq!(count_stats("e\u{301}".as_bytes())?.graphemes, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_display_width() -> io::Result<()> {
+        let options = CountOptions {
+            display_width: true,
+            ..Default::default()
+        };
+        let counts = count_stats_with_options("你好\n".as_bytes(), &options)?;
+
+        assert_eq!(counts.chars, 3);
+        assert_eq!(counts.display_width, Some(4)); // 全角文字は2列
+
+        let counts = count_stats_with_options("ab\ne\u{301}".as_bytes(), &options)?;
+        assert_eq!(counts.display_width, Some(3)); // 結合文字は0列
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_matching_lines() -> io::Result<()> {
+        let count = |pattern: &str, invert: bool| -> io::Result<Option<usize>> {
+            let options = CountOptions {
+                count_lines_matching: Some(Regex::new(pattern).unwrap()),
+                invert,
+                ..Default::default()
+            };
+            let text = "ERROR disk full\nINFO ok\nERROR ERROR twice\n\nWARN error lowercase\n";
+            Ok(count_stats_with_options(text.as_bytes(), &options)?.matching_lines)
+        };
+        // 1行に複数回一致しても1行として数える
+        assert_eq!(count("ERROR", false)?, Some(2));
+        assert_eq!(count("(?i)error", false)?, Some(3));
+        // 一致しない行には空行も含む
+        assert_eq!(count("ERROR", true)?, Some(3));
+        assert_eq!(count("nothing", false)?, Some(0));
+        assert_eq!(count("nothing", true)?, Some(5));
+
+        assert_eq!(count_stats("ERROR\n".as_bytes())?.matching_lines, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_matches() -> io::Result<()> {
+        let options = CountOptions {
+            match_pattern: Some(Regex::new(r"\d+\.\d+\.\d+\.\d+").unwrap()),
+            ..Default::default()
+        };
+        let text = "ERROR 10.0.0.1\nINFO 10.0.0.2 -> 192.168.1.1\nERROR none\n";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.matches, Some(3));
+
+        let options = CountOptions {
+            match_pattern: Some(Regex::new("aa").unwrap()),
+            ..Default::default()
+        };
+        // 重ならない一致のみを数える
+        let counts = count_stats_with_options("aaaa\n".as_bytes(), &options)?;
+        assert_eq!(counts.matches, Some(2));
+
+        let overlapping = |pattern: &str, text: &str| -> io::Result<Option<usize>> {
+            let options = CountOptions {
+                match_pattern: Some(Regex::new(pattern).unwrap()),
+                overlapping: true,
+                ..Default::default()
+            };
+            Ok(count_stats_with_options(text.as_bytes(), &options)?.matches)
+        };
+        // 重なる一致も数える
+        assert_eq!(overlapping("aa", "aaaa\n")?, Some(3));
+        assert_eq!(overlapping("ああ", "ああああ\n")?, Some(3));
+        // 空文字列に一致するパターンでも文字の境界ごとに1回ずつ数えて終わる
+        assert_eq!(overlapping("", "ab\n")?, Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_stats_invalid_utf8() -> io::Result<()> {
+        // Latin-1 で書かれた "café\n"
+        let input: &[u8] = b"caf\xe9\n";
+
+        // UTF-8 では中断せずに置換文字として数える
+        let counts = count_stats(input)?;
+        assert!(counts.lossy);
+        assert_eq!(counts.chars, 5);
+        assert_eq!(counts.bytes, 5);
+
+        for encoding in [Encoding::Latin1, Encoding::Auto] {
+            let options = CountOptions {
+                encoding,
+                ..Default::default()
+            };
+            let counts = count_stats_with_options(input, &options)?;
+            assert!(!counts.lossy);
+            assert_eq!(counts.chars, 5);
+            assert_eq!(counts.words, 1);
+        }
+
+        // auto では正しいUTF-8はそのまま読む
+        let options = CountOptions {
+            encoding: Encoding::Auto,
+            ..Default::default()
+        };
+        assert_eq!(count_stats_with_options("café\n".as_bytes(), &options)?.chars, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_gzip() -> io::Result<()> {
+        let text = "Hello, World!\nThis is a test.\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes())?;
+        let compressed = encoder.finish()?;
+
+        // 拡張子が .gz でなくてもマジックナンバーで判定する
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(&compressed)?;
+
+        let options = CountOptions {
+            decompress: true,
+            ..Default::default()
+        };
+        let stats = process_file(temp_file.path(), &options)?;
+        assert_eq!(stats.counts, count_stats(text.as_bytes())?);
+
+        // 展開しない場合は圧縮されたバイト列をそのまま数える
+        let stats = process_file(temp_file.path(), &CountOptions::default())?;
+        assert_eq!(stats.counts.bytes, compressed.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_range() -> io::Result<()> {
+        let text = "line one\nline two\nline three\n";
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(text.as_bytes())?;
+
+        // 2行目（9バイト目から18バイト目の手前まで）のみを数える
+        let options = CountOptions {
+            range: Some(9..18),
+            ..Default::default()
+        };
+        let stats = process_file(temp_file.path(), &options)?;
+        assert_eq!(stats.counts, count_stats("line two\n".as_bytes())?);
+        // メモリマップした場合も同じ範囲を数える
+        let mmap = CountOptions {
+            mmap: true,
+            ..options.clone()
+        };
+        assert_eq!(process_file(temp_file.path(), &mmap)?.counts, stats.counts);
+        // 行の位置は範囲の先頭ではなくファイルの先頭から数える
+        let positions = CountOptions {
+            positions: true,
+            ..options.clone()
+        };
+        assert_eq!(process_file(temp_file.path(), &positions)?.counts.line_offsets, Some(vec![9]));
+
+        // ファイルの末尾を超える範囲はファイルの大きさに収める
+        for range in [18..1000, 1000..2000] {
+            let expected = count_stats(text.as_bytes().get(range.start as usize..).unwrap_or(b""))?;
+            for mmap in [false, true] {
+                let options = CountOptions {
+                    range: Some(range.clone()),
+                    mmap,
+                    ..Default::default()
+                };
+                assert_eq!(process_file(temp_file.path(), &options)?.counts, expected);
+            }
+        }
+
+        // 圧縮されたファイルは展開した内容の範囲を数える
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes())?;
+        let mut gz_file = NamedTempFile::new()?;
+        gz_file.write_all(&encoder.finish()?)?;
+        let options = CountOptions {
+            range: Some(9..18),
+            decompress: true,
+            ..Default::default()
+        };
+        assert_eq!(process_file(gz_file.path(), &options)?.counts, stats.counts);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_mmap_matches_streaming() -> io::Result<()> {
+        let streaming = CountOptions {
+            word_frequencies: true,
+            graphemes: true,
+            display_width: true,
+            word_lengths: true,
+            ..Default::default()
+        };
+        let mapped = CountOptions {
+            mmap: true,
+            ..streaming.clone()
+        };
+
+        // 複数バイトの文字・不正なバイト列・末尾に改行のない行・空のファイル
+        let inputs: [&[u8]; 3] = [
+            "こんにちは 世界\r\n\nHello, World! e\u{301}\n".as_bytes(),
+            b"caf\xe9 au lait\nlast line",
+            b"",
+        ];
+        for input in inputs {
+            let mut temp_file = NamedTempFile::new()?;
+            temp_file.write_all(input)?;
+            let expected = process_file(temp_file.path(), &streaming)?;
+            let actual = process_file(temp_file.path(), &mapped)?;
+            assert_eq!(actual, expected);
+        }
+
+        Ok(())
+    }
+
+    /// 1回だけリクエストに応答するHTTPサーバーを起動し、そのURLを返す
+    fn serve_once(response: String) -> io::Result<String> {
+        serve_sequence(vec![response])
+    }
+
+    /// リクエストごとに `responses` を順に返すHTTPサーバーを起動し、そのURLを返す
+    fn serve_sequence(responses: Vec<String>) -> io::Result<String> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/sample.txt", listener.local_addr()?);
+        std::thread::spawn(move || for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            // リクエストヘッダーの終わりまで読み込んでから応答する
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        Ok(url)
+    }
+
+    /// ステータスコードのみで本文が空のレスポンス
+    fn status_response(status: &str) -> String {
+        format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status)
+    }
+
+    #[test]
+    fn test_process_file_url() -> io::Result<()> {
+        let body = "Hello, World!\nThis is a test.\n";
+        let url = serve_once(format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ))?;
+
+        let stats = process_file(Path::new(&url), &CountOptions::default())?;
+        assert_eq!(stats.filename, url);
+        assert_eq!(stats.counts, count_stats(body.as_bytes())?);
+
+        // エラーのステータスコードはファイルごとのエラーになる
+        let url = serve_once(
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string(),
+        )?;
+        let err = process_file(Path::new(&url), &CountOptions::default()).unwrap_err();
+        assert!(matches!(err, ProcessError::Network(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_url_timeout() -> io::Result<()> {
+        // 接続を受け付けるが応答しないサーバー
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/stalled.txt", listener.local_addr()?);
+        std::thread::spawn(move || {
+            let _connections: Vec<_> = listener.incoming().collect();
+        });
+
+        let options = CountOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let err = process_file(Path::new(&url), &options).unwrap_err();
+        assert!(matches!(err, ProcessError::Network(ureq::Error::Timeout(_))), "{err:?}");
+        assert!(err.to_string().starts_with("could not fetch: "));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_url_retries() -> io::Result<()> {
+        let body = "retried\n";
+        let ok = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let options = CountOptions {
+            retries: 2,
+            retry_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+
+        // 最初の2回が一時的なエラーでも、再試行して取得できる
+        let url = serve_sequence(vec![
+            status_response("503 Service Unavailable"),
+            status_response("503 Service Unavailable"),
+            ok.clone(),
+        ])?;
+        let stats = process_file(Path::new(&url), &options)?;
+        assert_eq!(stats.counts, count_stats(body.as_bytes())?);
+
+        // 再試行の回数を超えて失敗した場合はエラーになる
+        let url = serve_sequence(vec![
+            status_response("503 Service Unavailable"),
+            status_response("502 Bad Gateway"),
+            status_response("500 Internal Server Error"),
+            ok.clone(),
+        ])?;
+        let err = process_file(Path::new(&url), &options).unwrap_err();
+        assert!(matches!(err, ProcessError::Network(ureq::Error::StatusCode(500))));
+
+        // 本文の途中で接続が切れた場合も、取得し直して最初から数え直す
+        let truncated = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\nretr",
+            body.len()
+        );
+        let url = serve_sequence(vec![truncated.clone(), ok.clone()])?;
+        let stats = process_file(Path::new(&url), &options)?;
+        assert_eq!(stats.counts, count_stats(body.as_bytes())?);
+        let url = serve_sequence(vec![truncated.clone(), ok.clone()])?;
+        let stats = process_concat(&[PathBuf::from(&url)], &options, |_, e| panic!("{e}"))?;
+        assert_eq!(stats.counts, count_stats(body.as_bytes())?);
+        let no_retry = CountOptions {
+            retries: 0,
+            ..options.clone()
+        };
+        let url = serve_sequence(vec![truncated, ok.clone()])?;
+        let err = process_file(Path::new(&url), &no_retry).unwrap_err();
+        assert!(matches!(err, ProcessError::Network(ureq::Error::Io(_))), "{err:?}");
+
+        // 一時的でないエラーは再試行しない
+        let url = serve_sequence(vec![status_response("404 Not Found"), ok])?;
+        let err = process_file(Path::new(&url), &options).unwrap_err();
+        assert!(matches!(err, ProcessError::Network(ureq::Error::StatusCode(404))));
+
+        // ローカルのファイルが存在しない場合も再試行せずにすぐエラーになる
+        let options = CountOptions {
+            retries: 3,
+            retry_delay: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let dir = tempfile::tempdir()?;
+        let err = process_file(&dir.path().join("missing.txt"), &options).unwrap_err();
+        assert!(matches!(err, ProcessError::NotFound));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_concat() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second.txt");
+        let third = dir.path().join("third.txt");
+        // 1つ目は改行で終わらないため、末尾の単語が2つ目の先頭の単語とつながる
+        fs::write(&first, "one two")?;
+        fs::write(&second, "three\nfour\n")?;
+        fs::write(&third, "five\n")?;
+
+        let mut failed = Vec::new();
+        let paths = [first.clone(), second.clone()];
+        let stats = process_concat(&paths, &CountOptions::default(), |path, _| {
+            failed.push(path.to_path_buf())
+        })?;
+        assert_eq!(stats.filename, "2 files");
+        assert_eq!(stats.counts, count_stats("one twothree\nfour\n".as_bytes())?);
+        assert_eq!((stats.counts.lines, stats.counts.words), (2, 3));
+        // ファイルごとに数えて合計した場合は単語がつながらない
+        let mut total = process_file(&first, &CountOptions::default())?;
+        total.merge(&process_file(&second, &CountOptions::default())?);
+        assert_eq!(total.counts.words, 4);
+        assert!(failed.is_empty());
+
+        // 改行で終わる入力の後では単語はつながらず、開けない入力は報告して読み飛ばす
+        let missing = dir.path().join("missing.txt");
+        let paths = [second, missing.clone(), third];
+        let stats = process_concat(&paths, &CountOptions::default(), |path, _| {
+            failed.push(path.to_path_buf())
+        })?;
+        assert_eq!(stats.filename, "2 files");
+        assert_eq!((stats.counts.lines, stats.counts.words), (3, 3));
+        assert_eq!(failed, [missing]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_input() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("ok.txt");
+        fs::write(&file, "text\n")?;
+        let options = CountOptions {
+            decompress: true,
+            ..Default::default()
+        };
+        assert!(check_input(&file, &options).is_ok());
+        assert!(check_input(Path::new("-"), &options).is_ok());
+        assert!(matches!(
+            check_input(&dir.path().join("missing.txt"), &options),
+            Err(ProcessError::NotFound)
+        ));
+        assert!(matches!(check_input(dir.path(), &options), Err(ProcessError::IsDirectory)));
+        // 拡張子が `.zip` でもZIPアーカイブとして読めない場合はエラー
+        let broken = dir.path().join("broken.zip");
+        fs::write(&broken, "not a zip")?;
+        assert!(matches!(check_input(&broken, &options), Err(ProcessError::Archive(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_tar() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let append = |builder: &mut tar::Builder<_>, path: &str, data: &[u8]| -> io::Result<()> {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, data)
+        };
+        let build = |writer: Box<dyn Write>| -> io::Result<()> {
+            let mut builder = tar::Builder::new(writer);
+            append(&mut builder, "a.txt", b"hello world\n")?;
+            let mut dir_header = tar::Header::new_gnu();
+            dir_header.set_entry_type(tar::EntryType::Directory);
+            dir_header.set_size(0);
+            builder.append_data(&mut dir_header, "docs/", io::empty())?;
+            append(&mut builder, "docs/b.md", b"one\ntwo three\n")?;
+            let mut link_header = tar::Header::new_gnu();
+            link_header.set_entry_type(tar::EntryType::Symlink);
+            link_header.set_size(0);
+            builder.append_link(&mut link_header, "link.txt", "a.txt")?;
+            builder.into_inner()?.flush()
+        };
+
+        let plain = dir.path().join("bundle.tar");
+        build(Box::new(File::create(&plain)?))?;
+        let compressed = dir.path().join("bundle.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(&compressed)?,
+            flate2::Compression::default(),
+        );
+        build(Box::new(encoder))?;
+
+        // ディレクトリとシンボリックリンクは数えず、各ファイルを `archive.tar:entry` の名前で数える
+        let options = CountOptions {
+            decompress: true,
+            ..Default::default()
+        };
+        for path in [&plain, &compressed] {
+            let stats = process_input(path, &options)?;
+            let tar_name = path.to_string_lossy();
+            assert_eq!(stats.len(), 2);
+            assert_eq!(stats[0].filename, format!("{tar_name}:a.txt"));
+            assert_eq!(stats[0].counts, count_stats(&b"hello world\n"[..])?);
+            assert_eq!(stats[1].filename, format!("{tar_name}:docs/b.md"));
+            assert_eq!((stats[1].counts.lines, stats[1].counts.words), (2, 3));
+        }
+
+        // 展開しない場合はアーカイブ自体を1つのファイルとして数える
+        let stats = process_input(&plain, &CountOptions::default())?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].filename, plain.to_string_lossy());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_zip() -> Result<(), Box<dyn std::error::Error>> {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("bundle.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path)?);
+        writer.start_file("a.txt", SimpleFileOptions::default())?;
+        writer.write_all(b"hello world\n")?;
+        writer.add_directory("docs/", SimpleFileOptions::default())?;
+        writer.start_file("docs/b.md", SimpleFileOptions::default())?;
+        writer.write_all(b"one\ntwo three\n")?;
+        writer.finish()?;
+
+        // ディレクトリは数えず、各ファイルを `archive.zip:entry` の名前で数える
+        let options = CountOptions {
+            decompress: true,
+            ..Default::default()
+        };
+        let stats = process_input(&path, &options)?;
+        let zip_name = path.to_string_lossy();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].filename, format!("{zip_name}:a.txt"));
+        assert_eq!((stats[0].counts.lines, stats[0].counts.words), (1, 2));
+        assert_eq!(stats[0].counts.bytes, 12);
+        assert_eq!(stats[1].filename, format!("{zip_name}:docs/b.md"));
+        assert_eq!((stats[1].counts.lines, stats[1].counts.words), (2, 3));
+
+        // パターンに一致するファイルのみを数える
+        let options = CountOptions {
+            decompress: true,
+            zip_entries: Some(Pattern::new("*.md")?),
+            ..Default::default()
+        };
+        let stats = process_input(&path, &options)?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].filename, format!("{zip_name}:docs/b.md"));
+
+        // 展開しない場合はアーカイブ自体を1つのファイルとして数える
+        let stats = process_input(&path, &CountOptions::default())?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].filename, zip_name);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_rejects_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = process_file(dir.path(), &CountOptions::default()).unwrap_err();
+        assert!(matches!(err, ProcessError::IsDirectory));
+
+        let missing = dir.path().join("missing.txt");
+        let err = process_file(&missing, &CountOptions::default()).unwrap_err();
+        assert!(matches!(err, ProcessError::NotFound));
+    }
+
+    #[test]
+    fn test_walk_dir() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("b.txt"), "b\n")?;
+        fs::write(dir.path().join("a.txt"), "a\n")?;
+        fs::write(dir.path().join("sub").join("c.txt"), "c\n")?;
+
+        let files = walk_dir(dir.path(), &WalkOptions::default())?;
+
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("a.txt"),
+                dir.path().join("b.txt"),
+                dir.path().join("sub").join("c.txt"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_dir_exclude() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("vendor"))?;
+        fs::create_dir(dir.path().join("src"))?;
+        fs::write(dir.path().join("app.js"), "a\n")?;
+        fs::write(dir.path().join("app.min.js"), "a\n")?;
+        fs::write(dir.path().join("src").join("lib.js"), "b\n")?;
+        fs::write(dir.path().join("src").join("lib.min.js"), "b\n")?;
+        fs::write(dir.path().join("vendor").join("dep.js"), "c\n")?;
+
+        let options = WalkOptions {
+            exclude: vec![Pattern::new("*.min.js").unwrap(), Pattern::new("vendor").unwrap()],
+            ..Default::default()
+        };
+        let files = walk_dir(dir.path(), &options)?;
+
+        assert_eq!(
+            files,
+            vec![dir.path().join("app.js"), dir.path().join("src").join("lib.js")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_dir_extensions() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("docs"))?;
+        fs::write(dir.path().join("a.txt"), "a\n")?;
+        fs::write(dir.path().join("B.MD"), "b\n")?;
+        fs::write(dir.path().join("debug.log"), "c\n")?;
+        fs::write(dir.path().join("README"), "d\n")?;
+        fs::write(dir.path().join("docs").join("guide.md"), "e\n")?;
+        let walk = |include: &[&str], exclude: &[&str]| {
+            let options = WalkOptions {
+                include_ext: include.iter().map(|ext| ext.to_string()).collect(),
+                exclude_ext: exclude.iter().map(|ext| ext.to_string()).collect(),
+                ..Default::default()
+            };
+            walk_dir(dir.path(), &options)
+        };
+
+        // 大文字小文字を区別せず、サブディレクトリのファイルも絞り込む
+        assert_eq!(
+            walk(&["txt", ".md"], &[])?,
+            vec![
+                dir.path().join("B.MD"),
+                dir.path().join("a.txt"),
+                dir.path().join("docs").join("guide.md"),
+            ]
+        );
+        // 拡張子のないファイルは除外のみの場合は含める
+        assert_eq!(
+            walk(&[], &["log"])?,
+            vec![
+                dir.path().join("B.MD"),
+                dir.path().join("README"),
+                dir.path().join("a.txt"),
+                dir.path().join("docs").join("guide.md"),
+            ]
+        );
+        // 両方を指定した場合は含める拡張子で絞り込んでから除く
+        assert_eq!(walk(&["txt", "md"], &["MD"])?, vec![dir.path().join("a.txt")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_dir_modified_since() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let old = dir.path().join("old.txt");
+        let fresh = dir.path().join("fresh.txt");
+        fs::write(&old, "old\n")?;
+        fs::write(&fresh, "fresh\n")?;
+        let now = SystemTime::now();
+        let two_days = Duration::from_secs(2 * 24 * 60 * 60);
+        File::options().write(true).open(&old)?.set_modified(now - two_days)?;
+
+        let options = WalkOptions {
+            modified_since: Some(now - Duration::from_secs(24 * 60 * 60)),
+            ..Default::default()
+        };
+        assert_eq!(walk_dir(dir.path(), &options)?, vec![fresh.clone()]);
+        // 指定しない場合は更新日時によらず含める
+        assert_eq!(walk_dir(dir.path(), &WalkOptions::default())?, vec![fresh, old]);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_dir_symlink_loop() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("sub").join("a.txt"), "a\n")?;
+        // sub/loop -> 親ディレクトリ
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("sub").join("loop"))?;
+
+        let options = WalkOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let files = walk_dir(dir.path(), &options)?;
+
+        assert_eq!(files, vec![dir.path().join("sub").join("a.txt")]);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_dir_symlinks() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let target = tempfile::tempdir()?;
+        fs::write(dir.path().join("a.txt"), "a\n")?;
+        fs::write(target.path().join("b.txt"), "b\n")?;
+        std::os::unix::fs::symlink(dir.path().join("a.txt"), dir.path().join("file_link"))?;
+        std::os::unix::fs::symlink(target.path(), dir.path().join("dir_link"))?;
+
+        // デフォルトではファイルへのリンクのみ数え、ディレクトリへのリンクは辿らない
+        let files = walk_dir(dir.path(), &WalkOptions::default())?;
+        assert_eq!(files, vec![dir.path().join("a.txt"), dir.path().join("file_link")]);
+
+        let options = WalkOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let files = walk_dir(dir.path(), &options)?;
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("a.txt"),
+                dir.path().join("dir_link").join("b.txt"),
+                dir.path().join("file_link"),
+            ]
+        );
+
+        Ok(())
+    }
+}
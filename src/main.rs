@@ -1,18 +1,202 @@
-use clap::Parser;
-use serde::Serialize;
+mod output;
+mod watch;
+
+use clap::error::ErrorKind;
+use clap::{ArgGroup, CommandFactory, Parser, ValueEnum};
+use glob::Pattern;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use regex::Regex;
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, Read};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Seek, Write};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use terminal_size::Width;
+use text_stats::{
+    check_input, count_stats_with_options, is_tar, is_url, is_zip, process_concat,
+    process_input, read_input, total_stats, walk_dir,
+    CountOptions, Encoding, HashAlgorithm, Normalization, Counts, ProcessError, Stats, StatsDiff,
+    WalkOptions, DEFAULT_TAB_WIDTH,
+};
+
+/// `--sort` で並べ替えの基準にする項目
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    /// 行数
+    Lines,
+    /// 単語数
+    Words,
+    /// 文字数
+    Chars,
+    /// バイト数
+    Bytes,
+    /// ファイル名（辞書順）
+    Name,
+}
+
+/// `--fields` で列として表示する項目
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// 行数
+    Lines,
+    /// 単語数
+    Words,
+    /// 文字数
+    Chars,
+    /// バイト数
+    Bytes,
+    /// 空白文字を除いた文字数
+    CharsNoSpaces,
+    /// 最長行の文字数
+    MaxLineLength,
+    /// 最長行の行番号
+    MaxLineNumber,
+    /// 最短行の文字数
+    MinLineLength,
+    /// 文の数
+    Sentences,
+    /// 段落の数
+    Paragraphs,
+    /// 空行の数
+    EmptyLines,
+    /// 空白のみの行の数
+    BlankLines,
+    /// 末尾に空白のある行の数
+    TrailingWsLines,
+    /// 文字（アルファベット・かな・漢字など）の数
+    Letters,
+    /// 数字の数
+    Digits,
+    /// 空白文字の数
+    Whitespace,
+    /// 記号などの数
+    Punctuation,
+    /// ASCII以外の文字の数
+    NonAscii,
+}
+
+impl Field {
+    /// 表の見出し
+    fn header(self) -> &'static str {
+        match self {
+            Field::Lines => "LINES",
+            Field::Words => "WORDS",
+            Field::Chars => "CHARS",
+            Field::Bytes => "BYTES",
+            Field::CharsNoSpaces => "NO-SPACE",
+            Field::MaxLineLength => "MAX LINE",
+            Field::MaxLineNumber => "MAX LINE NO",
+            Field::MinLineLength => "MIN LINE",
+            Field::Sentences => "SENTENCES",
+            Field::Paragraphs => "PARAGRAPHS",
+            Field::EmptyLines => "EMPTY",
+            Field::BlankLines => "BLANK",
+            Field::TrailingWsLines => "TRAILING WS",
+            Field::Letters => "LETTERS",
+            Field::Digits => "DIGITS",
+            Field::Whitespace => "SPACES",
+            Field::Punctuation => "PUNCT",
+            Field::NonAscii => "NON-ASCII",
+        }
+    }
+
+    /// カウント結果から項目の値を取り出す関数
+    fn value(self, counts: &Counts) -> usize {
+        match self {
+            Field::Lines => counts.lines,
+            Field::Words => counts.words,
+            Field::Chars => counts.chars,
+            Field::Bytes => counts.bytes,
+            Field::CharsNoSpaces => counts.chars_no_spaces,
+            Field::MaxLineLength => counts.max_line_length,
+            Field::MaxLineNumber => counts.max_line_number,
+            Field::MinLineLength => counts.min_line_length,
+            Field::Sentences => counts.sentences,
+            Field::Paragraphs => counts.paragraphs,
+            Field::EmptyLines => counts.empty_lines,
+            Field::BlankLines => counts.blank_lines,
+            Field::TrailingWsLines => counts.trailing_ws_lines,
+            Field::Letters => counts.letters,
+            Field::Digits => counts.digits,
+            Field::Whitespace => counts.whitespace,
+            Field::Punctuation => counts.punctuation,
+            Field::NonAscii => counts.non_ascii,
+        }
+    }
+}
+
+/// `--output-template` の書式の一部分
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    /// そのまま出力する文字列
+    Text(String),
+    /// `{filename}`
+    Filename,
+    /// `{lines}` などの項目
+    Field(Field),
+}
+
+/// `--output-template` で指定された1ファイル分の行の書式
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template(Vec<TemplatePart>);
+
+/// 環境変数 `TEXT_STATS_DEFAULT_FORMAT` で既定にできる出力形式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// テーブル形式
+    Table,
+    /// JSON形式
+    Json,
+    /// 1行のJSON形式
+    JsonCompact,
+    /// JSON Lines形式
+    Ndjson,
+    /// CSV形式
+    Csv,
+    /// YAML形式
+    Yaml,
+    /// TOML形式
+    Toml,
+    /// Markdownの表形式
+    Markdown,
+    /// ファイルごとの結果と合計をまとめたJSON形式
+    SummaryJson,
+}
+
+/// テーブル形式で色を付けるかどうか
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    /// 標準出力が端末の場合のみ色を付ける
+    Auto,
+    /// 常に色を付ける
+    Always,
+    /// 色を付けない
+    Never,
+}
+
+/// 出力形式のオプションを指定しない場合の出力形式を指定する環境変数
+const DEFAULT_FORMAT_ENV: &str = "TEXT_STATS_DEFAULT_FORMAT";
 
 /// コマンドライン引数を定義する構造体
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(group(ArgGroup::new("format").args(["json", "json_compact", "summary_json", "ndjson", "csv", "yaml", "toml", "markdown"])))]
 struct Args {
     /// 入力ファイル（- で標準入力）
-    #[arg(required = true)]
+    #[arg(required_unless_present_any = ["files0_from", "files_from", "schema"])]
     files: Vec<PathBuf>,
 
+    /// NUL文字区切りのファイル名一覧をファイルから読み込む（- で標準入力）
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["files", "files_from"])]
+    files0_from: Option<PathBuf>,
+
+    /// 改行区切りのファイル名一覧をファイルから読み込み、引数のファイルに追加する（- で標準入力）
+    #[arg(long, value_name = "PATH")]
+    files_from: Option<PathBuf>,
+
     /// 行数のみをカウント
     #[arg(short, long)]
     lines: bool,
@@ -22,156 +206,2016 @@ struct Args {
     words: bool,
 
     /// 文字数のみをカウント
-    #[arg(short, long)]
+    #[arg(short = 'm', long)]
     chars: bool,
 
+    /// バイト数のみをカウント
+    #[arg(short = 'c', long)]
+    bytes: bool,
+
+    /// 最長行の文字数のみを表示
+    #[arg(short = 'L', long = "max-line-length")]
+    max_line_length: bool,
+
+    /// 表示する項目をカンマ区切りで指定した順に列として表示（`-l` `-w` などより優先。例: `--fields words,lines`）
+    #[arg(long, value_name = "FIELDS", value_enum, value_delimiter = ',')]
+    fields: Option<Vec<Field>>,
+
+    /// 書記素クラスタ（見た目上の文字）の数も表示
+    #[arg(long)]
+    graphemes: bool,
+
+    /// 端末上の表示幅（全角文字は2列）の合計も表示
+    #[arg(long)]
+    width: bool,
+
+    /// 文の数もテーブルに表示
+    #[arg(long)]
+    sentences: bool,
+
+    /// 段落（空行で区切られたブロック）の数もテーブルに表示
+    #[arg(long)]
+    paragraphs: bool,
+
+    /// 正規表現に一致した回数も表示
+    #[arg(long = "match", value_name = "PATTERN", value_parser = Regex::new)]
+    match_pattern: Option<Regex>,
+
+    /// `--match` で重なる一致も数える（`aaaa` の中の `aa` を2回ではなく3回と数える）
+    #[arg(long, requires = "match_pattern")]
+    overlapping: bool,
+
+    /// 正規表現に一致する行の数も表示（`grep -c` と同様）
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    count_lines_matching: Option<Regex>,
+
+    /// `--count-lines-matching` で一致しない行の数を数える（`grep -vc` と同様）
+    #[arg(long, requires = "count_lines_matching")]
+    invert: bool,
+
+    /// 行の区切りとする1文字（ASCII）。`\0` `\t` `\n` `\r` のエスケープも使える
+    #[arg(long, value_name = "CHAR", value_parser = parse_delimiter)]
+    delimiter: Option<u8>,
+
+    /// 行数を `wc -l` と同様に改行（`--delimiter` の区切り）の数として数え、末尾に改行のない最終行を数えない
+    #[arg(long)]
+    count_newlines: bool,
+
+    /// N行ごとに1行（1, N+1, 2N+1, ...行目）のみを数える（巨大なファイルの概算向け）
+    #[arg(long, value_name = "N")]
+    every_nth: Option<NonZeroUsize>,
+
+    /// `--every-nth` で数えた件数をN倍して全体を推定する
+    #[arg(long, requires = "every_nth")]
+    extrapolate: bool,
+
+    /// 入力のバイト範囲 `START:END`（`END` の手前まで）のみを数える（例: `--range 0:1048576` で先頭1MB）
+    #[arg(long, value_name = "START:END", value_parser = parse_range)]
+    range: Option<Range<u64>>,
+
+    /// 正規表現に一致する行のみを集計（`grep PATTERN | text-stats` と同様）
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    filter: Option<Regex>,
+
+    /// 単語とみなす正規表現（指定しない場合は空白で区切る）
+    #[arg(long, value_name = "PATTERN", value_parser = Regex::new)]
+    word_regex: Option<Regex>,
+
+    /// 単語とみなす最小の文字数（書記素ではなく文字数）。これより短い単語は単語数・出現回数に数えない
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    min_word_length: usize,
+
+    /// 最長・最短の単語と単語の平均文字数もテーブルに表示
+    #[arg(long)]
+    word_lengths: bool,
+
+    /// 単語の文字数ごとの単語の数（`1:5 2:3 ...`）もテーブルに表示
+    #[arg(long)]
+    word_length_dist: bool,
+
+    /// 文字・数字・空白・記号ごとの文字数もテーブルに表示
+    #[arg(long)]
+    classify: bool,
+
+    /// 単語の種類の数（語彙数）もテーブルに表示
+    #[arg(long)]
+    unique_words: bool,
+
+    /// 逆から読んでも同じ行（回文）の数もテーブルに表示
+    #[arg(long)]
+    palindromes: bool,
+
+    /// 行頭の字下げがタブ・空白・混在の行の数もテーブルに表示し、タブと空白を混ぜたファイルを標準エラーに報告
+    #[arg(long)]
+    indentation: bool,
+
+    /// 各行の文字を逆順に並べ替えた内容を数える（双方向テキストの処理の検証用）
+    #[arg(long)]
+    reversed: bool,
+
+    /// 空白文字を除いた文字数もテーブルに表示（ワープロの「文字数（スペースを含めない）」）
+    #[arg(long)]
+    chars_no_spaces: bool,
+
+    /// 指定した文字ごとの出現回数もテーブルに表示（例: `--count-chars ',;'`。タブはシェルで `$'\t'` と入力する）
+    #[arg(long, value_name = "CHARS")]
+    count_chars: Option<String>,
+
+    /// ASCII以外の文字を含むかどうかとその数をテーブルに表示し、含むファイルがあれば異常終了
+    #[arg(long)]
+    ascii_only: bool,
+
+    /// 1行あたりの平均単語数と1単語あたりの平均文字数もテーブルに表示
+    #[arg(long)]
+    averages: bool,
+
+    /// 最短・最長・平均の行の文字数もテーブルに表示
+    #[arg(long)]
+    line_stats: bool,
+
+    /// 最長行の文字数と表示幅を測るときのタブ幅（0 でタブを1文字として数える）
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_TAB_WIDTH)]
+    tab_width: usize,
+
+    /// 空行と空白のみの行の数もテーブルに表示
+    #[arg(long)]
+    blank_lines: bool,
+
+    /// 末尾に空白のある行の数もテーブルに表示し、1行でもあれば終了コード1で終了（CI向け）
+    #[arg(long)]
+    trailing_whitespace: bool,
+
+    /// 改行コード（CRLF・LF・単独のCR）ごとの数もテーブルに表示
+    #[arg(long)]
+    line_endings: bool,
+
+    /// ディレクトリを再帰的に走査して含まれるファイルを処理
+    #[arg(short = 'r', long = "recursive")]
+    recursive: bool,
+
+    /// 再帰モードでディレクトリへのシンボリックリンクも辿る
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// 再帰モードでファイル名またはパスがパターンに一致するものを除外（複数指定可）
+    #[arg(long, value_name = "GLOB", requires = "recursive", value_parser = Pattern::new)]
+    exclude: Vec<Pattern>,
+
+    /// 再帰モードで指定した拡張子のファイルのみを処理（カンマ区切りで複数指定可。大文字小文字は区別しない）
+    #[arg(long, value_name = "EXT", requires = "recursive", value_delimiter = ',')]
+    include_ext: Vec<String>,
+
+    /// 再帰モードで指定した拡張子のファイルを除外（カンマ区切りで複数指定可。`--include-ext` の後に適用）
+    #[arg(long, value_name = "EXT", requires = "recursive", value_delimiter = ',')]
+    exclude_ext: Vec<String>,
+
+    /// 再帰モードで指定した期間内に更新されたファイルのみを処理（`30m` `24h` `7d` のように分・時間・日で指定）
+    #[arg(long, value_name = "DURATION", requires = "recursive", value_parser = parse_since)]
+    since: Option<Duration>,
+
+    /// グロブや再帰的な走査で展開した後の先頭N件のファイルのみを処理（`--sort` の並べ替えより前に絞り込む）
+    #[arg(long, value_name = "N")]
+    limit: Option<NonZeroUsize>,
+
+    /// `--top-words` `--unique-words` で大文字小文字を区別しない（デフォルト）
+    #[arg(long, overrides_with = "no_ignore_case")]
+    ignore_case: bool,
+
+    /// `--top-words` `--unique-words` で大文字小文字を区別する（`The` と `the` を別の単語とする）
+    #[arg(long, overrides_with = "ignore_case")]
+    no_ignore_case: bool,
+
+    /// 通常の集計の代わりに出現回数の多い単語を上位N件表示（出力形式は `--json` のみ指定可）
+    #[arg(long, value_name = "N", conflicts_with_all = [
+        "json_compact", "summary_json", "ndjson", "csv", "yaml", "toml", "markdown",
+    ])]
+    top_words: Option<usize>,
+
+    /// 通常の集計の代わりに行の文字数の分布を棒グラフで表示
+    #[arg(long, conflicts_with_all = ["format", "top_words"])]
+    histogram: bool,
+
+    /// `--histogram` で1本の棒にまとめる文字数の幅
+    #[arg(long, value_name = "N", default_value = "10", requires = "histogram")]
+    bucket_size: NonZeroUsize,
+
+    /// 内容のダイジェストを指定したハッシュ関数で求めてテーブルに表示（合計行は空欄）
+    #[arg(long, value_name = "ALGO", value_enum)]
+    hash: Option<HashAlgorithm>,
+
+    /// 各行の先頭のバイト位置をJSON・YAML形式の出力に `line_offsets` として含める（テーブル形式には表示しない）
+    #[arg(long)]
+    positions: bool,
+
+    /// 数える前にUnicodeの正規化形式をそろえる（指定しない場合は正規化しない）
+    #[arg(long, value_name = "FORM", value_enum)]
+    normalize: Option<Normalization>,
+
+    /// 数える前に端末の色などのエスケープシーケンス（ANSI CSI）を取り除く（バイト数は元の入力のまま）
+    #[arg(long)]
+    strip_ansi: bool,
+
+    /// 入力ファイルの文字コード
+    #[arg(long, value_enum, default_value_t = Encoding::Utf8)]
+    encoding: Encoding,
+
+    /// gzip圧縮されたファイル・ZIPアーカイブ・tarアーカイブを展開せずにそのまま数える
+    #[arg(long)]
+    no_decompress: bool,
+
+    /// ZIPアーカイブ・tarアーカイブ内のパスがパターンに一致するファイルのみを数える
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        value_parser = Pattern::new,
+        conflicts_with = "no_decompress"
+    )]
+    zip_only: Option<Pattern>,
+
+    /// URLの取得が一時的なネットワークのエラーで失敗した場合に再試行する回数
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// 最初に再試行するまでの待ち時間（ミリ秒）。再試行するたびに倍にする
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    retry_delay: u64,
+
+    /// URLの取得（本文の読み込みまで）を打ち切るまでの秒数
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 30,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    timeout: u64,
+
+    /// 通常のファイルをメモリマップして読み込む（大きなファイル向け）
+    #[arg(long)]
+    mmap: bool,
+
+    /// ストリームから読み込むときのバッファのバイト数（大きなファイルの読み込みの調整用）
+    #[arg(long, value_name = "BYTES", default_value = "65536")]
+    buffer_size: NonZeroUsize,
+
+    /// 最初に数えた後もファイルを監視し、変更されるたびに数え直して表示
+    #[arg(long)]
+    watch: bool,
+
+    /// ファイルごとの処理時間と全体の経過時間を標準エラーに表示
+    #[arg(long)]
+    timing: bool,
+
+    /// 結果を表示した後、各ファイルをメモリに読み込んでからN回数え直し、1回あたりの平均の処理時間と処理速度を標準エラーに表示（計測用）
+    #[arg(long, value_name = "N", hide = true, conflicts_with = "watch")]
+    repeat: Option<NonZeroUsize>,
+
+    /// 処理済みのファイル数を標準エラーに表示（標準出力が端末でない場合は指定しなくても表示）
+    #[arg(long)]
+    progress: bool,
+
+    /// ファイルを数えずに、開けるかどうかのみを確認して `OK` `ERROR` を表示
+    #[arg(long, conflicts_with = "watch")]
+    dry_run: bool,
+
+    /// 2つのファイルの行数・単語数・文字数の差（2つ目 − 1つ目）を表示
+    #[arg(long, conflicts_with_all = [
+        "json_compact", "summary_json", "ndjson", "csv", "yaml", "toml", "markdown",
+        "output_template", "top_words", "histogram", "watch", "summary_only",
+    ])]
+    compare: bool,
+
+    /// 全てのファイルを順に連結した1つの文書として数え、1件の結果を表示（`cat a b | text-stats -` と同じ）
+    #[arg(long, conflicts_with = "compare")]
+    concat: bool,
+
+    /// 結果のファイル名を指定したディレクトリからの相対パスで表示（ディレクトリの外のファイルはそのまま）
+    #[arg(long, value_name = "DIR")]
+    relative_to: Option<PathBuf>,
+
+    /// 標準入力（`-`）の結果に表示するファイル名（例: `--stdin-name clipboard`）
+    #[arg(long, value_name = "NAME")]
+    stdin_name: Option<String>,
+
+    /// 空のファイル（行・単語・文字が0）を結果と合計から除く
+    #[arg(long)]
+    skip_empty: bool,
+
+    /// 空のファイル（0バイトまたは0行）があれば、結果を表示した後にファイル名を報告して終了コード1で終了
+    #[arg(long)]
+    fail_on_empty: bool,
+
+    /// バイナリファイル（先頭8KBにNULを含むファイル）を結果と合計から除き、標準エラーに表示
+    #[arg(long)]
+    text_only: bool,
+
+    /// 指定した項目の昇順で結果を並べ替える（合計行は常に末尾）
+    #[arg(long, value_enum, value_name = "FIELD")]
+    sort: Option<SortKey>,
+
+    /// `--sort` の並び順を降順にする
+    #[arg(long, requires = "sort")]
+    reverse: bool,
+
+    /// ファイルごとの結果を表示せず、合計のみを表示
+    #[arg(long)]
+    summary_only: bool,
+
+    /// 並列に処理するスレッド数の上限（省略時はCPU数）
+    #[arg(long, value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+
+    /// 結果を標準出力ではなく指定したファイルに書き込む（`-` で標準出力）
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
     /// JSON形式で出力
     #[arg(short, long)]
     json: bool,
+
+    /// 改行や字下げを含まない1行のJSON形式で出力（プログラム間での受け渡し向け）
+    #[arg(long)]
+    json_compact: bool,
+
+    /// ファイルごとの結果の配列 `files` と合計 `total` を1つのオブジェクトにまとめたJSON形式で出力
+    #[arg(long, conflicts_with = "summary_only")]
+    summary_json: bool,
+
+    /// 各ファイル（と合計）を1行ずつ指定した書式で出力（例: `'{filename} {lines} {words} {chars}'`。`{filename}` と `--fields` の項目を置き換え、`{{` `}}` で括弧そのもの）
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        value_parser = parse_template,
+        conflicts_with_all = ["format", "top_words", "histogram"]
+    )]
+    output_template: Option<Template>,
+
+    /// JSON・YAML形式で入力が1つの場合に、配列で囲まず単一のオブジェクトとして出力
+    #[arg(long)]
+    unwrap_single: bool,
+
+    /// 1ファイル1行のJSON（JSON Lines）形式で出力
+    #[arg(long)]
+    ndjson: bool,
+
+    /// CSV形式で出力
+    #[arg(long)]
+    csv: bool,
+
+    /// YAML形式で出力
+    #[arg(long)]
+    yaml: bool,
+
+    /// TOML形式で出力（各ファイルを `[[files]]` の表として出力）
+    #[arg(long)]
+    toml: bool,
+
+    /// Markdownの表形式で出力
+    #[arg(long)]
+    markdown: bool,
+
+    /// テーブル形式で数値を3桁ごとにカンマで区切る（例: 1,234,567）
+    #[arg(long)]
+    thousands_sep: bool,
+
+    /// テーブル形式でヘッダー行と区切り線を表示しない
+    #[arg(long)]
+    no_header: bool,
+
+    /// テーブル形式で最も行数の多いファイルを緑、空のファイルを赤で表示する
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// JSON出力の各ファイルの項目を表すJSON Schemaを表示して終了（ファイルは読み込まない）
+    #[arg(long, hide = true)]
+    schema: bool,
 }
 
-/// ファイルの統計情報を保持する構造体
-#[derive(Serialize)]
-struct Stats {
-    filename: String,  // ファイル名
-    lines: usize,      // 行数
-    words: usize,      // 単語数
-    chars: usize,      // 文字数
+impl Args {
+    /// 結果を標準出力に書き込むかどうか（`--output` を指定しないか `-` を指定した場合）
+    fn writes_to_stdout(&self) -> bool {
+        self.output.as_ref().is_none_or(|path| path.as_os_str() == "-")
+    }
+
+    /// テーブル形式の出力に色を付けるかどうかを判定する関数
+    fn use_color(&self) -> bool {
+        match self.color {
+            ColorChoice::Auto => self.writes_to_stdout() && io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+
+    /// 出力形式のオプションが指定されていなければ、環境変数で指定された出力形式にする関数
+    /// 環境変数が空の場合は指定していないものとし、不正な値の場合はオプションの指定によらずエラーにする
+    fn apply_default_format(&mut self) -> Result<(), clap::Error> {
+        let value = match std::env::var(DEFAULT_FORMAT_ENV) {
+            Ok(value) if !value.is_empty() => value,
+            _ => return Ok(()),
+        };
+        let format = OutputFormat::from_str(&value, true).map_err(|_| {
+            let possible: Vec<_> = OutputFormat::value_variants()
+                .iter()
+                .filter_map(|format| format.to_possible_value())
+                .map(|format| format.get_name().to_string())
+                .collect();
+            Args::command().error(
+                ErrorKind::InvalidValue,
+                format!(
+                    "invalid value '{}' for {} (possible values: {})",
+                    value,
+                    DEFAULT_FORMAT_ENV,
+                    possible.join(", ")
+                ),
+            )
+        })?;
+
+        let formats = [
+            self.json,
+            self.json_compact,
+            self.summary_json,
+            self.ndjson,
+            self.csv,
+            self.yaml,
+            self.toml,
+            self.markdown,
+        ];
+        if formats.contains(&true) {
+            return Ok(());
+        }
+        match format {
+            OutputFormat::Table => {}
+            OutputFormat::Json => self.json = true,
+            OutputFormat::JsonCompact => self.json_compact = true,
+            OutputFormat::SummaryJson => self.summary_json = true,
+            OutputFormat::Ndjson => self.ndjson = true,
+            OutputFormat::Csv => self.csv = true,
+            OutputFormat::Yaml => self.yaml = true,
+            OutputFormat::Toml => self.toml = true,
+            OutputFormat::Markdown => self.markdown = true,
+        }
+        Ok(())
+    }
+
+    /// `--count-chars` で指定された文字を重複を除いて文字コード順に返す関数
+    fn char_set(&self) -> Vec<char> {
+        let mut chars: Vec<char> = self.count_chars.iter().flat_map(|s| s.chars()).collect();
+        chars.sort_unstable();
+        chars.dedup();
+        chars
+    }
+
+    /// clapでは表現できない引数の組み合わせを検証する関数
+    fn validate(&self) -> Result<(), clap::Error> {
+        // 標準入力は一度しか読めないため、`-` の複数指定はエラーにする
+        let stdin_count = self.files.iter().filter(|path| path.as_os_str() == "-").count();
+        if stdin_count > 1 {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "'-' (standard input) can only be given once",
+            ));
+        }
+        // 標準入力は変更を監視できない
+        if self.watch && stdin_count > 0 {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "'-' (standard input) cannot be used with --watch",
+            ));
+        }
+        let list_from_stdin = self.files_from.as_ref().is_some_and(|list| list.as_os_str() == "-");
+        if list_from_stdin && stdin_count > 0 {
+            return Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "'-' cannot be counted when --files-from reads from standard input",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `--range` の値（`START:END`）をバイト範囲に変換する関数
+/// `START` を省略した場合は先頭から、`END` を省略した場合は末尾までとする
+fn parse_range(value: &str) -> Result<Range<u64>, String> {
+    let (start, end) = value
+        .split_once(':')
+        .ok_or_else(|| "expected START:END (for example 0:1048576)".to_string())?;
+    let parse = |n: &str, default| {
+        if n.is_empty() {
+            Ok(default)
+        } else {
+            n.parse::<u64>().map_err(|e| format!("invalid offset '{}': {}", n, e))
+        }
+    };
+    let range = parse(start, 0)?..parse(end, u64::MAX)?;
+    if range.start > range.end {
+        return Err("START must not be greater than END".to_string());
+    }
+    Ok(range)
+}
+
+/// `--since` の値（`30m` `24h` `7d` など）を期間に変換する関数
+/// 単位は `m`（分）・`h`（時間）・`d`（日）のいずれかとする
+fn parse_since(value: &str) -> Result<Duration, String> {
+    let unit_secs = match value.chars().last() {
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return Err("expected a number followed by m, h or d (for example 24h)".to_string()),
+    };
+    let n = &value[..value.len() - 1];
+    let n: u64 = n.parse().map_err(|e| format!("invalid number '{}': {}", n, e))?;
+    n.checked_mul(unit_secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| "the duration is too long".to_string())
+}
+
+/// `--output-template` の値を書式に変換する関数
+/// `{filename}` と `--fields` で指定できる項目（`{max-line-length}` は `{max_line_length}` とも書ける）を置き換え、
+/// `{{` `}}` はそれぞれ `{` `}` とする。それ以外の置き換えの指定はエラーにする
+fn parse_template(value: &str) -> Result<Template, String> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '{' | '}' if rest.starts_with(c) => {
+                text.push(c);
+                rest = &rest[1..];
+            }
+            '{' => {
+                let (name, after) = rest.split_once('}').ok_or_else(|| {
+                    "unclosed '{' in template (use '{{' for a literal brace)".to_string()
+                })?;
+                let part = if name == "filename" {
+                    TemplatePart::Filename
+                } else {
+                    let field = Field::from_str(&name.replace('_', "-"), false).map_err(|_| {
+                        let names: Vec<_> = Field::value_variants()
+                            .iter()
+                            .filter_map(|field| field.to_possible_value())
+                            .map(|field| field.get_name().to_string())
+                            .collect();
+                        format!(
+                            "unknown placeholder '{{{}}}' (available: filename, {})",
+                            name,
+                            names.join(", ")
+                        )
+                    })?;
+                    TemplatePart::Field(field)
+                };
+                if !text.is_empty() {
+                    parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(part);
+                rest = after;
+            }
+            '}' => {
+                return Err("unmatched '}' in template (use '}}' for a literal brace)".to_string());
+            }
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(TemplatePart::Text(text));
+    }
+    Ok(Template(parts))
+}
+
+/// `--delimiter` の値を区切りのバイトに変換する関数
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "\\0" => Ok(b'\0'),
+        "\\t" => Ok(b'\t'),
+        "\\n" => Ok(b'\n'),
+        "\\r" => Ok(b'\r'),
+        _ => match value.as_bytes() {
+            [byte] if byte.is_ascii() => Ok(*byte),
+            _ => Err("the delimiter must be a single ASCII character".to_string()),
+        },
+    }
+}
+
+/// バイト列をパスに変換する関数
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+/// バイト列をパスに変換する関数
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// NUL文字区切りのファイル名一覧を読み込む関数（`find -print0` の出力など）
+/// 空のファイル名（末尾のNUL文字の後など）は無視する
+fn read_files0<R: Read>(mut reader: R) -> io::Result<Vec<PathBuf>> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    Ok(buf
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+/// 改行区切りのファイル名一覧を読み込む関数（マニフェストファイルなど）
+/// 空行と `#` で始まる行（コメント）は無視する
+fn read_files_lines<R: BufRead>(reader: R) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let line = line.strip_suffix(b"\r").unwrap_or(&line);
+        if line.trim_ascii().is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        files.push(path_from_bytes(line));
+    }
+    Ok(files)
+}
+
+/// `--files0-from` や `--files-from` で指定されたファイルからファイル名一覧を読み込む関数
+/// 一覧の形式は `parse` で解釈する
+fn read_file_list<F>(list: &Path, parse: F) -> io::Result<Vec<PathBuf>>
+where
+    F: Fn(Box<dyn BufRead>) -> io::Result<Vec<PathBuf>>,
+{
+    if list.as_os_str() != "-" {
+        return parse(Box::new(BufReader::new(File::open(list)?)));
+    }
+
+    let files = parse(Box::new(io::stdin().lock()))?;
+    // 標準入力は一覧の読み込みに使ったため、ファイルとしては読めない
+    if files.iter().any(|path| path.as_os_str() == "-") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "when reading file names from standard input, '-' is not allowed",
+        ));
+    }
+    Ok(files)
+}
+
+/// グロブパターンを一致するパスの一覧に展開する関数
+/// シェルが展開しない環境（Windowsや引用符で囲んだ場合）でもパターンを使えるようにする。
+/// メタ文字を含まないパスや、そのままの名前で存在するパスは展開しない
+fn expand_glob(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let pattern = path.to_string_lossy();
+    // URLのクエリ文字列の `?` などはパターンとして扱わない
+    if !pattern.contains(['*', '?', '[']) || path.exists() || is_url(path) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let paths = glob::glob(&pattern)
+        .map_err(|e| format!("invalid pattern: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    if paths.is_empty() {
+        return Err("no files match the pattern".to_string());
+    }
+    Ok(paths)
+}
+
+/// 引数のパスを処理対象のファイル一覧に展開する関数
+/// グロブパターンを展開し、再帰モード（`walk_options` を指定した場合）ではディレクトリを
+/// 走査して含まれるファイルに置き換える
+///
+/// # 戻り値
+/// * 処理対象のファイル一覧と、エラーが発生したかどうか
+fn expand_paths(
+    paths: &[PathBuf],
+    walk_options: Option<&WalkOptions>,
+) -> (Vec<PathBuf>, bool) {
+    let mut files = Vec::new();
+    let mut had_error = false;
+
+    for pattern in paths {
+        let matched = match expand_glob(pattern) {
+            Ok(matched) => matched,
+            Err(e) => {
+                eprintln!("Error processing {}: {}", pattern.display(), e);
+                had_error = true;
+                continue;
+            }
+        };
+
+        for path in matched {
+            let walk_options = walk_options.filter(|_| path.is_dir());
+            if let Some(walk_options) = walk_options {
+                match walk_dir(&path, walk_options) {
+                    Ok(found) => files.extend(found),
+                    Err(e) => {
+                        eprintln!("Error processing {}: {}", path.display(), e);
+                        had_error = true;
+                    }
+                }
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    (files, had_error)
+}
+
+/// ファイルの処理中に標準エラーへ表示する情報の設定
+struct Reporter {
+    progress: ProgressBar,  // ファイルを1つ処理するごとに進める進捗バー
+    timing: bool,  // ファイルごとの処理時間を表示するかどうか
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Reporter {
+            progress: ProgressBar::hidden(),
+            timing: false,
+        }
+    }
 }
 
-/// テキストの統計情報をカウントする関数
-/// ファイル全体をバッファに読み込んでカウントする
-fn count_stats<R: Read>(mut reader: R) -> io::Result<(usize, usize, usize)> {
-    let mut buf = String::new();
-    reader.read_to_string(&mut buf)?;
-    let lines = buf.lines().count();
-    let words = buf.split_whitespace().count();
-    let chars = buf.chars().count();
-    Ok((lines, words, chars))
+/// ファイルを処理できなかった理由を標準エラーに出力する関数
+fn report_error(path: &Path, e: &ProcessError) {
+    match e {
+        ProcessError::IsDirectory => eprintln!(
+            "Error processing {}: is a directory (use -r to count the files in it)",
+            path.display()
+        ),
+        ProcessError::Encoding(e) => eprintln!(
+            "Error processing {}: could not decode the contents: {}",
+            path.display(),
+            e
+        ),
+        e => eprintln!("Error processing {}: {}", path.display(), e),
+    }
+}
+
+/// `--concat` で全てのファイルを連結した1つの文書として統計情報を求める関数
+/// 開けなかったファイルは `collect_stats` と同じく標準エラーに出力して読み飛ばす
+///
+/// # 戻り値
+/// * 連結した内容の統計情報（読み込みの途中で失敗した場合は空）と、エラーが発生したかどうか
+fn concat_stats(files: &[PathBuf], options: &CountOptions) -> (Vec<Stats>, bool) {
+    let mut had_error = false;
+    let result = process_concat(files, options, |path, e| {
+        report_error(path, &e);
+        had_error = true;
+    });
+    match result {
+        Ok(stat) => {
+            if stat.counts.lossy {
+                eprintln!("Warning: {}: invalid UTF-8 was replaced with U+FFFD", stat.filename);
+            }
+            (vec![stat], had_error)
+        }
+        Err(e) => {
+            eprintln!("Error processing concatenated input: {}", e);
+            (Vec::new(), true)
+        }
+    }
+}
+
+/// 各ファイルを並列に処理して統計情報を集める関数
+/// 結果は入力の順序のまま返し、エラーが発生したファイルは標準エラーに出力してスキップする
+///
+/// # 戻り値
+/// * 成功したファイルの統計情報と、エラーが発生したかどうか
+fn collect_stats(
+    files: &[PathBuf],
+    options: &CountOptions,
+    reporter: &Reporter,
+) -> (Vec<Stats>, bool) {
+    let started = Instant::now();
+    // `-` は validate で1回までに制限しているため、標準入力が並行して読まれることはない
+    let results: Vec<_> = files
+        .par_iter()
+        .map(|path| {
+            let file_started = Instant::now();
+            let result = process_input(path, options);
+            reporter.progress.inc(1);
+            (result, file_started.elapsed())
+        })
+        .collect();
+    // エラーや警告のメッセージと混ざらないよう、出力する前に消しておく
+    reporter.progress.finish_and_clear();
+
+    let mut stats = Vec::new();
+    let mut had_error = false;
+
+    for (path, (result, elapsed)) in files.iter().zip(results) {
+        if reporter.timing {
+            eprintln!("{}: {:.2}ms", path.display(), elapsed.as_secs_f64() * 1000.0);
+        }
+        match result {
+            Ok(file_stats) => {
+                for stat in file_stats {
+                    if stat.counts.lossy {
+                        eprintln!(
+                            "Warning: {}: invalid UTF-8 was replaced with U+FFFD",
+                            stat.filename
+                        );
+                    }
+                    stats.push(stat);
+                }
+            }
+            Err(e) => {
+                report_error(path, &e);
+                // 残りのファイルの処理は続行する
+                had_error = true;
+            }
+        }
+    }
+    if reporter.timing {
+        // 並列に処理するため、各ファイルの処理時間の合計ではなく全体の経過時間を表示する
+        eprintln!("total: {:.2}ms", started.elapsed().as_secs_f64() * 1000.0);
+    }
+
+    (stats, had_error)
+}
+
+/// 処理済みのファイル数と全体のファイル数を表示する進捗バーを作る関数
+/// 標準エラーに表示するため、標準出力の結果には混ざらない
+fn progress_bar(len: u64) -> ProgressBar {
+    let style = ProgressStyle::with_template("{bar:40} {pos}/{len} files")
+        .expect("valid progress template");
+    ProgressBar::new(len).with_style(style)
+}
+
+/// 統計情報を指定した項目で並べ替える関数
+/// 値が同じファイルは入力の順序を保つ
+fn sort_stats(stats: &mut [Stats], key: SortKey, reverse: bool) {
+    stats.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Lines => a.counts.lines.cmp(&b.counts.lines),
+            SortKey::Words => a.counts.words.cmp(&b.counts.words),
+            SortKey::Chars => a.counts.chars.cmp(&b.counts.chars),
+            SortKey::Bytes => a.counts.bytes.cmp(&b.counts.bytes),
+            SortKey::Name => a.filename.cmp(&b.filename),
+        };
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// 出力する統計情報の一覧を作る関数
+/// 複数ファイルの場合は末尾に合計行を追加し、`summary_only` の場合は合計行のみにする
+fn with_total(mut stats: Vec<Stats>, summary_only: bool) -> Vec<Stats> {
+    if summary_only {
+        return vec![total_stats(&stats)];
+    }
+    if stats.len() > 1 {
+        let total = total_stats(&stats);
+        stats.push(total);
+    }
+    stats
 }
 
-/// ファイルを処理して統計情報を取得する関数
-/// 
-/// # 引数
-/// * `path` - 処理するファイルのパス
-/// 
+/// 空のファイル（0バイトまたは0行）の名前を入力の順に返す関数
+/// `--filter` で一致する行がなかったファイルも0行として含める
+fn empty_files(stats: &[Stats]) -> Vec<String> {
+    stats
+        .iter()
+        .filter(|stat| stat.counts.bytes == 0 || stat.counts.lines == 0)
+        .map(|stat| stat.filename.clone())
+        .collect()
+}
+
+/// 表示するファイル名を `base` からの相対パスにする関数
+/// `./` の有無などで書き方が異なる場合も比べられるよう、そのままで前方一致しなければ絶対パスにして比べる
+///
 /// # 戻り値
-/// * `Stats` - ファイルの統計情報
-fn process_file(path: &PathBuf) -> io::Result<Stats> {
-    let filename = path.to_string_lossy().to_string();
-    // 標準入力の場合はstdinを使用、それ以外はファイルを開く
-    let reader: Box<dyn Read> = if path.to_string_lossy() == "-" {
-        Box::new(io::stdin())
+/// * `base` の中のファイルであれば相対パス、そうでなければ `None`
+fn relative_name(name: &str, base: &Path) -> Option<String> {
+    let path = Path::new(name);
+    if is_url(path) {
+        return None;
+    }
+    let relative = match path.strip_prefix(base) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => {
+            let path = std::path::absolute(path).ok()?;
+            path.strip_prefix(std::path::absolute(base).ok()?).ok()?.to_path_buf()
+        }
+    };
+    Some(relative.to_string_lossy().into_owned())
+}
+
+/// 集めた統計情報を出力する順に整える関数
+/// 標準入力の名前を付け替えてファイル名を相対パスにし、空のファイルやバイナリファイルを除き、並べ替えてから合計行を追加する
+fn prepare_stats(mut stats: Vec<Stats>, args: &Args) -> Vec<Stats> {
+    if let Some(name) = &args.stdin_name {
+        // `-` は validate で1回までに制限しているため、付け替えるのは多くとも1件
+        if let Some(stat) = stats.iter_mut().find(|stat| stat.filename == "-") {
+            stat.filename = name.clone();
+        }
+    }
+    if let Some(base) = &args.relative_to {
+        for stat in &mut stats {
+            if let Some(name) = relative_name(&stat.filename, base) {
+                stat.filename = name;
+            }
+        }
+    }
+    if args.skip_empty {
+        stats.retain(|stat| !stat.counts.is_empty());
+    }
+    if args.text_only {
+        stats.retain(|stat| {
+            if stat.counts.is_binary {
+                eprintln!("Skipping {}: binary file", stat.filename);
+            }
+            !stat.counts.is_binary
+        });
+    }
+    // 合計行を追加する前に並べ替え、合計行が末尾に残るようにする
+    if let Some(key) = args.sort {
+        sort_stats(&mut stats, key, args.reverse);
+    }
+    // `--summary-json` は合計を別の項目として出力するため、合計行を追加しない
+    if args.summary_json {
+        return stats;
+    }
+    with_total(stats, args.summary_only)
+}
+
+/// 結果の出力先
+enum Output {
+    Stdout,
+    File(File),  // `--output` で指定したファイル
+}
+
+impl Output {
+    /// `--output` の指定に応じて出力先を開く関数
+    /// ファイルを数える前に開き、書き込めない場合はすぐにエラーにする
+    fn open(args: &Args) -> io::Result<Self> {
+        match &args.output {
+            Some(path) if !args.writes_to_stdout() => Ok(Output::File(File::create(path)?)),
+            _ => Ok(Output::Stdout),
+        }
+    }
+
+    /// 結果を書き込む関数
+    /// ファイルの場合は `--watch` で数え直したときに前回の結果が残らないよう、先頭から書き直す
+    fn write(&mut self, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        match self {
+            Output::Stdout => write(&mut io::stdout().lock()),
+            Output::File(file) => {
+                file.set_len(0)?;
+                file.rewind()?;
+                let mut out = io::BufWriter::new(file);
+                write(&mut out)?;
+                out.flush()
+            }
+        }
+    }
+
+    /// 出力先が端末かどうか
+    fn is_terminal(&self) -> bool {
+        matches!(self, Output::Stdout) && io::stdout().is_terminal()
+    }
+}
+
+/// ファイルを数えて結果を出力する関数
+///
+/// # 戻り値
+/// * エラーが発生したファイルがあるか、`--ascii-only` でASCII以外の文字を含むファイルがあるか、
+///   `--fail-on-empty` で空のファイルがあるかどうか
+fn run(
+    args: &Args,
+    files: &[PathBuf],
+    options: &CountOptions,
+    pool: &rayon::ThreadPool,
+    output: &mut Output,
+) -> io::Result<bool> {
+    let reporter = Reporter {
+        progress: if args.progress || !io::stdout().is_terminal() {
+            progress_bar(files.len() as u64)
+        } else {
+            ProgressBar::hidden()
+        },
+        timing: args.timing,
+    };
+    let (stats, had_error) = if args.concat {
+        concat_stats(files, options)
     } else {
-        Box::new(File::open(path)?)
+        pool.install(|| collect_stats(files, options, &reporter))
     };
+    if args.compare {
+        let mut too_many = false;
+        output.write(|mut out| {
+            too_many = compare(&mut out, &stats, args)?;
+            Ok(())
+        })?;
+        return Ok(had_error || too_many);
+    }
+    // `--skip-empty` で除く前に調べ、除いた場合も空のファイルとして報告する
+    let empty = if args.fail_on_empty { empty_files(&stats) } else { Vec::new() };
+    // 合計行を追加する前に調べ、ファイルごとに報告する
+    let mixed: Vec<String> = stats
+        .iter()
+        .filter(|stat| stat.counts.mixes_indentation())
+        .map(|stat| stat.filename.clone())
+        .collect();
+    let stats = prepare_stats(stats, args);
+
+    output.write(|mut out| write_output(&mut out, &stats, args))?;
+    for name in &empty {
+        eprintln!("Error: {} is empty", name);
+    }
+    for name in &mixed {
+        eprintln!("Warning: {} mixes tab and space indentation", name);
+    }
+
+    if let Some(n) = args.repeat {
+        let is_archive = |path: &Path| options.decompress && (is_zip(path) || is_tar(path));
+        for path in files.iter().filter(|path| path.is_file() && !is_archive(path)) {
+            if let Err(e) = benchmark(path, options, n) {
+                report_error(path, &e);
+            }
+        }
+    }
 
-    let (lines, words, chars) = count_stats(reader)?;
-    Ok(Stats {
-        filename,
-        lines,
-        words,
-        chars,
-    })
+    let has_non_ascii = args.ascii_only && stats.iter().any(|stat| stat.counts.non_ascii > 0);
+    let has_trailing_ws =
+        args.trailing_whitespace && stats.iter().any(|stat| stat.counts.trailing_ws_lines > 0);
+    Ok(had_error || has_non_ascii || has_trailing_ws || !empty.is_empty())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // コマンドライン引数を解析
-    let args = Args::parse();
-    let mut stats = Vec::new();
+/// `--repeat` で入力をメモリに読み込んでから `n` 回数え、1回あたりの平均の処理時間と処理速度を標準エラーに表示する関数
+/// 読み込みと展開の時間を含めないよう、通常どおり数える内容（gzipは展開後、`--range` の範囲）を読み込んでから
+/// 数える処理のみを計測する。通常のファイル以外（標準入力やURLなど）とZIP・tarアーカイブは計測しない
+///
+/// # 戻り値
+/// * 最後に数えた結果（通常どおり数えた結果と同じになる）
+fn benchmark(path: &Path, options: &CountOptions, n: NonZeroUsize) -> Result<Counts, ProcessError> {
+    let bytes = read_input(path, options)?;
+    // 読み込んだ内容は展開と範囲の切り出しを済ませているため、同じ処理を繰り返さない
+    let options = CountOptions {
+        decompress: false,
+        range: None,
+        ..options.clone()
+    };
+    let mut counts = Counts::default();
+    let started = Instant::now();
+    for _ in 0..n.get() {
+        counts = count_stats_with_options(bytes.as_slice(), &options)?;
+    }
+    let mean = started.elapsed().as_secs_f64() / n.get() as f64;
+    let throughput = if mean > 0.0 { bytes.len() as f64 / mean / 1e6 } else { 0.0 };
+    eprintln!(
+        "{}: {:.3}ms/run, {:.1} MB/s ({} runs)",
+        path.display(),
+        mean * 1000.0,
+        throughput,
+        n
+    );
+    Ok(counts)
+}
 
-    // 各ファイルを処理
-    for path in &args.files {
-        match process_file(path) {
-            Ok(stat) => stats.push(stat),
+/// `--dry-run` で各ファイルを数えずに開けるかどうかを確認し、ファイルごとに結果を表示する関数
+///
+/// # 戻り値
+/// * 開けないファイルがあったかどうか
+fn dry_run<W: Write>(out: &mut W, files: &[PathBuf], options: &CountOptions) -> io::Result<bool> {
+    let mut failed = false;
+    for path in files {
+        match check_input(path, options) {
+            Ok(()) => writeln!(out, "OK {}", path.display())?,
             Err(e) => {
-                eprintln!("Error processing {}: {}", path.display(), e);
-                // エラーが発生した場合、プログラムを終了
-                return Err(Box::new(e));
+                writeln!(out, "ERROR {}: {}", path.display(), e)?;
+                failed = true;
             }
         }
     }
+    Ok(failed)
+}
 
-    // 出力形式に応じて結果を表示
+/// `--compare` で2つのファイルの差を表示する関数
+/// 読み込めなかったファイルがあるなどで2件そろわない場合は何も表示しない
+///
+/// # 戻り値
+/// * ZIPアーカイブに複数のファイルが含まれていたなどで、比べる対象が2件より多かったかどうか
+fn compare<W: Write>(out: &mut W, stats: &[Stats], args: &Args) -> io::Result<bool> {
+    let [from, to] = stats else {
+        let too_many = stats.len() > 2;
+        if too_many {
+            eprintln!("Error: --compare requires exactly two files, got {}", stats.len());
+        }
+        return Ok(too_many);
+    };
+    let diff = StatsDiff::new(from, to);
     if args.json {
-        // JSON形式で出力
-        println!("{}", serde_json::to_string_pretty(&stats)?);
+        output::write_json(out, &diff)?;
     } else {
-        // テーブル形式のヘッダーを表示（全項目を表示する場合のみ）
-        if !args.lines && !args.words && !args.chars {
-            println!("{:>12} {:>12} {:>12} {:>12}", "FILE", "LINES", "WORDS", "CHARS");
-            println!("{:>12} {:>12} {:>12} {:>12}", "----", "-----", "-----", "-----");
-        }
-
-        // 各ファイルの統計情報を表示
-        for stat in stats {
-            if args.lines {
-                println!("{}: {} lines", stat.filename, stat.lines);
-            } else if args.words {
-                println!("{}: {} words", stat.filename, stat.words);
-            } else if args.chars {
-                println!("{}: {} chars", stat.filename, stat.chars);
-            } else {
-                println!(
-                    "{:>12} {:>12} {:>12} {:>12}",
-                    stat.filename, stat.lines, stat.words, stat.chars
-                );
+        output::write_diff(out, &diff)?;
+    }
+    Ok(false)
+}
+
+/// 出力形式に応じて結果を表示する関数
+fn write_output<W: Write>(out: &mut W, stats: &[Stats], args: &Args) -> io::Result<()> {
+    let single = args.summary_only || (args.unwrap_single && stats.len() == 1);
+    if let Some(n) = args.top_words {
+        if args.json {
+            output::write_top_words_json(out, stats, n)
+        } else {
+            output::write_top_words(out, stats, n)
+        }
+    } else if args.histogram {
+        // 端末でない場合や幅を取得できない場合は80列とする
+        let width = terminal_size::terminal_size().map_or(80, |(Width(width), _)| width.into());
+        output::write_histogram(out, stats, args.bucket_size.get(), width)
+    } else if let Some(template) = &args.output_template {
+        output::write_template(out, stats, template)
+    } else if args.json {
+        // 合計のみの場合や `--unwrap-single` で入力が1つの場合は配列ではなく単一のオブジェクトとして出力
+        if single {
+            output::write_json(out, &stats[0])
+        } else {
+            output::write_json(out, stats)
+        }
+    } else if args.json_compact {
+        if single {
+            output::write_json_compact(out, &stats[0])
+        } else {
+            output::write_json_compact(out, stats)
+        }
+    } else if args.summary_json {
+        output::write_summary_json(out, stats)
+    } else if args.ndjson {
+        output::write_ndjson(out, stats)
+    } else if args.csv {
+        output::write_csv(out, stats)
+    } else if args.yaml {
+        if single {
+            output::write_yaml(out, &stats[0])
+        } else {
+            output::write_yaml(out, stats)
+        }
+    } else if args.toml {
+        output::write_toml(out, stats)
+    } else if args.markdown {
+        output::write_markdown(out, stats, args)
+    } else {
+        output::write_table(out, stats, args)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // コマンドライン引数を解析
+    let mut args = Args::parse();
+    if let Err(e) = args.apply_default_format().and_then(|()| args.validate()) {
+        e.exit();
+    }
+    if args.schema {
+        output::write_schema(&mut io::stdout().lock())?;
+        return Ok(());
+    }
+
+    // 処理対象のファイル名一覧を取得
+    let listed = match (&args.files0_from, &args.files_from) {
+        (Some(list), _) => Some((list, read_file_list(list, read_files0))),
+        (None, Some(list)) => Some((list, read_file_list(list, read_files_lines))),
+        (None, None) => None,
+    };
+    let mut inputs = args.files.clone();
+    if let Some((list, result)) = listed {
+        match result {
+            Ok(files) => inputs.extend(files),
+            Err(e) => {
+                eprintln!("Error reading file list {}: {}", list.display(), e);
+                std::process::exit(1);
             }
         }
     }
 
+    // 出力先のファイルは数える前に開き、開けない場合は何も処理せずに終了する
+    let mut output = match Output::open(&args) {
+        Ok(output) => output,
+        Err(e) => {
+            let path = args.output.as_deref().unwrap_or(Path::new("-"));
+            eprintln!("Error opening output file {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    // 各ファイルを処理
+    let walk_options = WalkOptions {
+        follow_symlinks: args.follow_symlinks,
+        exclude: args.exclude.clone(),
+        include_ext: args.include_ext.clone(),
+        exclude_ext: args.exclude_ext.clone(),
+        // 期間が長すぎて時刻を表せない場合は全てのファイルを対象にする
+        modified_since: args
+            .since
+            .map(|since| SystemTime::now().checked_sub(since).unwrap_or(SystemTime::UNIX_EPOCH)),
+    };
+    let recursive = args.recursive.then_some(&walk_options);
+    let (mut files, expand_error) = expand_paths(&inputs, recursive);
+    if let Some(limit) = args.limit {
+        files.truncate(limit.get());
+    }
+    if args.compare && files.len() != 2 {
+        let message = format!("--compare requires exactly two files, got {}", files.len());
+        Args::command().error(ErrorKind::WrongNumberOfValues, message).exit();
+    }
+    let options = CountOptions {
+        word_frequencies: args.top_words.is_some(),
+        unique_words: args.unique_words,
+        case_sensitive: args.no_ignore_case,
+        graphemes: args.graphemes,
+        display_width: args.width,
+        encoding: args.encoding,
+        normalize: args.normalize,
+        strip_ansi: args.strip_ansi,
+        decompress: !args.no_decompress,
+        match_pattern: args.match_pattern.clone(),
+        count_lines_matching: args.count_lines_matching.clone(),
+        invert: args.invert,
+        overlapping: args.overlapping,
+        tab_width: Some(args.tab_width),
+        word_lengths: args.word_lengths,
+        word_length_dist: args.word_length_dist,
+        word_pattern: args.word_regex.clone(),
+        min_word_length: args.min_word_length,
+        mmap: args.mmap,
+        buffer_size: Some(args.buffer_size),
+        line_endings: args.line_endings,
+        line_filter: args.filter.clone(),
+        line_lengths: args.histogram,
+        positions: args.positions,
+        delimiter: args.delimiter,
+        count_newlines: args.count_newlines,
+        hash: args.hash,
+        every_nth: args.every_nth,
+        extrapolate: args.extrapolate,
+        range: args.range.clone(),
+        zip_entries: args.zip_only.clone(),
+        retries: args.retries,
+        retry_delay: Duration::from_millis(args.retry_delay),
+        timeout: Some(Duration::from_secs(args.timeout)),
+        // 追加の項目はライブラリから登録する
+        metrics: Vec::new(),
+        count_chars: args.char_set(),
+        palindromes: args.palindromes,
+        indentation: args.indentation,
+        reversed: args.reversed,
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.map_or(0, NonZeroUsize::get))
+        .build()?;
+    if args.dry_run {
+        let mut failed = false;
+        output.write(|mut out| {
+            failed = dry_run(&mut out, &files, &options)?;
+            Ok(())
+        })?;
+        if failed || expand_error {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let failed = run(&args, &files, &options, &pool, &mut output)? || expand_error;
+
+    // 監視モードでは変更されるたびに数え直し、Ctrl-C で終了するまで繰り返す
+    if args.watch {
+        let watcher = watch::FileWatcher::new(&files)?;
+        loop {
+            watcher.wait(None)?;
+            if output.is_terminal() {
+                print!("\x1b[2J\x1b[H");  // 前回の結果を消す
+            }
+            run(&args, &files, &options, &pool, &mut output)?;
+        }
+    }
+
+    // エラーが発生したファイルがあれば、全ての結果を表示した後に異常終了
+    if failed {
+        std::process::exit(1);
+    }
+
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
     use tempfile::NamedTempFile;
-    use std::io::Write;
-
-    #[test]
-    fn test_count_stats() -> io::Result<()> {
-        // 一時ファイルを作成
-        let mut temp_file = NamedTempFile::new()?;
-        temp_file.write_all("Hello, World!\nThis is a test.\n".as_bytes())?;
-        
-        let file = File::open(temp_file.path())?;
-        let (lines, words, chars) = count_stats(file)?;
-        
-        assert_eq!(lines, 2);
-        assert_eq!(words, 6);  // "Hello,", "World!", "This", "is", "a", "test."
-        assert_eq!(chars, 30); // 13+1+14+1+1=30 (including all newlines)
-        
-        Ok(())
-    }
-
-    #[test]
-    fn test_count_stats_empty() -> io::Result<()> {
-        let mut temp_file = NamedTempFile::new()?;
-        temp_file.write_all("".as_bytes())?;
-        
-        let file = File::open(temp_file.path())?;
-        let (lines, words, chars) = count_stats(file)?;
-        
-        assert_eq!(lines, 0);
-        assert_eq!(words, 0);
-        assert_eq!(chars, 0);
-        
-        Ok(())
-    }
-
-    #[test]
-    fn test_count_stats_multiple_lines() -> io::Result<()> {
-        let mut temp_file = NamedTempFile::new()?;
-        temp_file.write_all("Line 1\nLine 2\nLine 3\n".as_bytes())?;
-        
-        let file = File::open(temp_file.path())?;
-        let (lines, words, chars) = count_stats(file)?;
-        
-        assert_eq!(lines, 3);
-        assert_eq!(words, 6);  // "Line", "1", "Line", "2", "Line", "3"
-        assert_eq!(chars, 21); // 6+6+6+3(\n) = 21
-        
+
+    #[test]
+    fn test_validate_rejects_repeated_stdin() {
+        let args = Args::parse_from(["text-stats", "-", "-"]);
+        let err = args.validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+
+        let args = Args::parse_from(["text-stats", "-", "a.txt"]);
+        assert!(args.validate().is_ok());
+    }
+
+    #[test]
+    fn test_default_format_from_env() -> io::Result<()> {
+        let stats = vec![Stats {
+            filename: "a.txt".to_string(),
+            counts: Counts {
+                lines: 1,
+                ..Default::default()
+            },
+        }];
+        let output = |args: &Args| -> io::Result<String> {
+            let mut out = Vec::new();
+            write_output(&mut out, &stats, args)?;
+            Ok(String::from_utf8(out).unwrap())
+        };
+
+        // 環境変数を参照するテストはこの1つのみにして、並列に実行されても干渉しないようにする
+        std::env::set_var(DEFAULT_FORMAT_ENV, "CSV");
+        let mut args = Args::parse_from(["text-stats", "a.txt"]);
+        args.apply_default_format().unwrap();
+        assert!(output(&args)?.starts_with("filename,lines,words,chars,bytes\n"));
+
+        // オプションで指定した形式が優先される
+        let mut args = Args::parse_from(["text-stats", "--json", "a.txt"]);
+        args.apply_default_format().unwrap();
+        assert!(!args.csv);
+        assert!(output(&args)?.starts_with("[\n"));
+
+        std::env::set_var(DEFAULT_FORMAT_ENV, "xml");
+        let mut args = Args::parse_from(["text-stats", "a.txt"]);
+        let err = args.apply_default_format().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+        assert!(err.to_string().contains("possible values: table, json, json-compact, ndjson"));
+
+        std::env::remove_var(DEFAULT_FORMAT_ENV);
+        let mut args = Args::parse_from(["text-stats", "a.txt"]);
+        args.apply_default_format().unwrap();
+        assert!(output(&args)?.starts_with("FILE"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_case_last_flag_wins() {
+        let args = Args::parse_from(["text-stats", "--no-ignore-case", "a.txt"]);
+        assert!(args.no_ignore_case);
+        let args = Args::parse_from(["text-stats", "--no-ignore-case", "--ignore-case", "a.txt"]);
+        assert!(!args.no_ignore_case);
+    }
+
+    #[test]
+    fn test_read_files0() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second file.txt");
+        std::fs::write(&first, "one\n")?;
+        std::fs::write(&second, "two words\n")?;
+
+        let mut list = Vec::new();
+        for path in [&first, &second] {
+            list.extend_from_slice(path.to_string_lossy().as_bytes());
+            list.push(0);
+        }
+        list.push(0); // 空のファイル名は無視される
+
+        let files = read_files0(list.as_slice())?;
+        assert_eq!(files, vec![first, second]);
+
+        let (stats, had_error) =
+            collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        assert!(!had_error);
+        assert_eq!(stats[0].counts.words, 1);
+        assert_eq!(stats[1].counts.words, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_files_lines_skips_comments_and_blanks() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second file.txt");
+        std::fs::write(&first, "one\n")?;
+        std::fs::write(&second, "two words\n")?;
+
+        let mut manifest = NamedTempFile::new()?;
+        writeln!(manifest, "# 集計するファイル")?;
+        writeln!(manifest, "{}", first.display())?;
+        writeln!(manifest)?;
+        writeln!(manifest, "   ")?;
+        write!(manifest, "{}\r\n", second.display())?;
+
+        let files = read_file_list(manifest.path(), read_files_lines)?;
+        assert_eq!(files, vec![first, second]);
+
+        let (stats, had_error) =
+            collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        assert!(!had_error);
+        assert_eq!(stats.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffer_size_rejects_zero() {
+        let args = Args::try_parse_from(["text-stats", "--buffer-size", "4096", "a"]);
+        assert_eq!(args.ok().map(|args| args.buffer_size.get()), Some(4096));
+        for value in ["0", "64K"] {
+            let err = Args::try_parse_from(["text-stats", "--buffer-size", value, "a"])
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::ValueValidation, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("0:1048576"), Ok(0..1048576));
+        assert_eq!(parse_range("1024:"), Ok(1024..u64::MAX));
+        assert_eq!(parse_range(":10"), Ok(0..10));
+        assert!(parse_range("10:5").is_err());
+        assert!(parse_range("10").is_err());
+        assert!(parse_range("a:b").is_err());
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_since("24h"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(parse_since("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert!(parse_since("24").is_err());
+        assert!(parse_since("h").is_err());
+        assert!(parse_since("1.5h").is_err());
+        assert!(parse_since("2w").is_err());
+    }
+
+    #[test]
+    fn test_parse_template() {
+        use TemplatePart::*;
+        assert_eq!(
+            parse_template("{filename}: {lines} {max_line_length}"),
+            Ok(Template(vec![
+                Filename,
+                Text(": ".to_string()),
+                Field(self::Field::Lines),
+                Text(" ".to_string()),
+                Field(self::Field::MaxLineLength),
+            ]))
+        );
+        assert_eq!(
+            parse_template("{{{words}}}"),
+            Ok(Template(vec![
+                Text("{".to_string()),
+                Field(self::Field::Words),
+                Text("}".to_string()),
+            ]))
+        );
+        assert!(parse_template("{unknown}").unwrap_err().contains("'{unknown}'"));
+        assert!(parse_template("{lines").is_err());
+        assert!(parse_template("lines}").is_err());
+    }
+
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_delimiter("\\0"), Ok(b'\0'));
+        assert_eq!(parse_delimiter(","), Ok(b','));
+        assert!(parse_delimiter("ab").is_err());
+        assert!(parse_delimiter("、").is_err());
+    }
+
+    #[test]
+    fn test_invalid_match_pattern_is_rejected() {
+        let err = Args::try_parse_from(["text-stats", "--match", "(unclosed", "a.txt"])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_top_words_rejects_formats_other_than_json() {
+        let formats = [
+            "--json-compact", "--summary-json", "--ndjson", "--csv", "--yaml", "--toml", "--markdown",
+        ];
+        for format in formats {
+            let err = Args::try_parse_from(["text-stats", format, "--top-words", "2", "a.txt"])
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict, "{format}");
+        }
+        assert!(Args::try_parse_from(["text-stats", "--json", "--top-words", "2", "a.txt"]).is_ok());
+    }
+
+    #[test]
+    fn test_collect_stats_continues_after_error() -> io::Result<()> {
+        let mut valid = NamedTempFile::new()?;
+        valid.write_all("Hello, World!\n".as_bytes())?;
+        let missing = valid.path().with_extension("missing");
+
+        let files = vec![missing, valid.path().to_path_buf()];
+        let (stats, had_error) =
+            collect_stats(&files, &CountOptions::default(), &Reporter::default());
+
+        assert!(had_error);
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].filename, valid.path().to_string_lossy());
+        assert_eq!(stats[0].counts.words, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_stats_preserves_order_in_parallel() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files: Vec<PathBuf> = (0..100)
+            .map(|i| {
+                let path = dir.path().join(format!("{}.txt", i));
+                std::fs::write(&path, "line\n".repeat(i)).map(|_| path)
+            })
+            .collect::<io::Result<_>>()?;
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let (stats, had_error) = pool.install(|| {
+            collect_stats(&files, &CountOptions::default(), &Reporter::default())
+        });
+
+        assert!(!had_error);
+        assert_eq!(stats.len(), files.len());
+        for (i, (stat, path)) in stats.iter().zip(&files).enumerate() {
+            assert_eq!(stat.filename, path.to_string_lossy());
+            assert_eq!(stat.counts.lines, i);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_stats_progress_does_not_change_stats() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files: Vec<PathBuf> = (0..10)
+            .map(|i| {
+                let path = dir.path().join(format!("{}.txt", i));
+                std::fs::write(&path, "word ".repeat(i)).map(|_| path)
+            })
+            .collect::<io::Result<_>>()?;
+
+        let reporter = Reporter {
+            progress: progress_bar(files.len() as u64),
+            ..Default::default()
+        };
+        let (with_progress, _) = collect_stats(&files, &CountOptions::default(), &reporter);
+        let (without_progress, _) =
+            collect_stats(&files, &CountOptions::default(), &Reporter::default());
+
+        assert_eq!(with_progress, without_progress);
+        assert_eq!(reporter.progress.position(), files.len() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_stats_timing_does_not_change_stats() -> io::Result<()> {
+        let args = Args::parse_from(["text-stats", "--timing", "a.txt"]);
+        assert!(args.timing);
+
+        let mut file = NamedTempFile::new()?;
+        file.write_all("Hello, World!\nLine 2\n".as_bytes())?;
+        let files = vec![file.path().to_path_buf()];
+
+        let reporter = Reporter {
+            timing: args.timing,
+            ..Default::default()
+        };
+        let (with_timing, _) = collect_stats(&files, &CountOptions::default(), &reporter);
+        let (without_timing, _) =
+            collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        assert_eq!(with_timing, without_timing);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_benchmark_matches_single_run() -> io::Result<()> {
+        let args = Args::parse_from(["text-stats", "--repeat", "3", "a.txt"]);
+        let n = args.repeat.unwrap();
+
+        let mut file = NamedTempFile::new()?;
+        file.write_all("Hello, World!\nこんにちは 世界\nno newline".as_bytes())?;
+
+        let options = CountOptions {
+            unique_words: true,
+            ..Default::default()
+        };
+        let repeated = benchmark(file.path(), &options, n).map_err(io::Error::other)?;
+        let single = process_input(file.path(), &options).map_err(io::Error::other)?;
+        assert_eq!(repeated, single[0].counts);
+
+        // 圧縮されたファイルは展開した内容を、`--range` は範囲の内容のみを数える
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all("one two\nthree\n".repeat(100).as_bytes())?;
+        let mut gz_file = tempfile::Builder::new().suffix(".gz").tempfile()?;
+        gz_file.write_all(&encoder.finish()?)?;
+        for range in [None, Some(8..100)] {
+            let options = CountOptions {
+                decompress: true,
+                range,
+                ..Default::default()
+            };
+            let repeated = benchmark(gz_file.path(), &options, n).map_err(io::Error::other)?;
+            let single = process_input(gz_file.path(), &options).map_err(io::Error::other)?;
+            assert_eq!(repeated, single[0].counts);
+        }
+        let options = CountOptions {
+            range: Some(0..10),
+            ..Default::default()
+        };
+        let repeated = benchmark(file.path(), &options, n).map_err(io::Error::other)?;
+        assert_eq!(repeated.bytes, 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_stats_skip_empty() -> io::Result<()> {
+        let empty = NamedTempFile::new()?;
+        let mut non_empty = NamedTempFile::new()?;
+        non_empty.write_all("Hello, World!\n".as_bytes())?;
+        let files = vec![empty.path().to_path_buf(), non_empty.path().to_path_buf()];
+
+        let (stats, _) = collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        let args = Args::parse_from(["text-stats", "a", "b"]);
+        assert_eq!(prepare_stats(stats, &args).len(), 3);
+
+        let (stats, _) = collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        let args = Args::parse_from(["text-stats", "--skip-empty", "a", "b"]);
+        let rows = prepare_stats(stats, &args);
+        // 空のファイルを除くと1ファイルになるため合計行も追加しない
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].filename, non_empty.path().to_string_lossy());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_stats_stdin_name() {
+        let stat = |filename: &str| Stats {
+            filename: filename.to_string(),
+            counts: Counts::default(),
+        };
+        let args = Args::parse_from(["text-stats", "--stdin-name", "clipboard", "a.txt", "-"]);
+        let rows = prepare_stats(vec![stat("a.txt"), stat("-")], &args);
+        let names: Vec<&str> = rows.iter().map(|stat| stat.filename.as_str()).collect();
+        assert_eq!(names, ["a.txt", "clipboard", "total"]);
+
+        // 指定しない場合は `-` のまま
+        let args = Args::parse_from(["text-stats", "-"]);
+        assert_eq!(prepare_stats(vec![stat("-")], &args)[0].filename, "-");
+    }
+
+    #[test]
+    fn test_prepare_stats_relative_to() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("docs"))?;
+        let inside = dir.path().join("docs/a.txt");
+        std::fs::write(&inside, "one two\n")?;
+        let outside = NamedTempFile::new()?;
+        let files = vec![inside.clone(), outside.path().to_path_buf()];
+        let (stats, _) = collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        let expected = stats[0].counts.lines;
+
+        let args = Args::parse_from([
+            "text-stats".as_ref(),
+            "--relative-to".as_ref(),
+            dir.path().as_os_str(),
+            "a".as_ref(),
+        ]);
+        let rows = prepare_stats(stats, &args);
+        // 基準のディレクトリの中のファイルは相対パスで表示し、数えた結果は変わらない
+        assert_eq!(Path::new(&rows[0].filename), Path::new("docs/a.txt"));
+        assert_eq!(rows[0].counts.lines, expected);
+        // 外のファイルはそのまま
+        assert_eq!(rows[1].filename, outside.path().to_string_lossy());
+
+        // `./` の有無など書き方が異なっても相対パスにする
+        assert_eq!(relative_name("./src/main.rs", Path::new("src")), Some("main.rs".into()));
+        assert_eq!(relative_name("-", Path::new("src")), None);
+        assert_eq!(relative_name("https://example.com/a.txt", Path::new(".")), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_stats_text_only() -> io::Result<()> {
+        let mut binary = NamedTempFile::new()?;
+        binary.write_all(b"GIF89a\x01\0\x01\0")?;
+        let mut text = NamedTempFile::new()?;
+        text.write_all("Hello, World!\n".as_bytes())?;
+        let files = vec![binary.path().to_path_buf(), text.path().to_path_buf()];
+
+        // 指定しない場合もバイナリファイルとして印を付けて数える
+        let (stats, _) = collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        assert!(stats[0].counts.is_binary);
+        assert!(!stats[1].counts.is_binary);
+        let args = Args::parse_from(["text-stats", "a", "b"]);
+        assert_eq!(prepare_stats(stats, &args).len(), 3);
+
+        let (stats, _) = collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        let args = Args::parse_from(["text-stats", "--text-only", "a", "b"]);
+        let rows = prepare_stats(stats, &args);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].filename, text.path().to_string_lossy());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_to_file() -> io::Result<()> {
+        let mut input = NamedTempFile::new()?;
+        input.write_all("one two\nthree\n".as_bytes())?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("result.json");
+        let args = Args::parse_from([
+            "text-stats".as_ref(),
+            "--json".as_ref(),
+            "--output".as_ref(),
+            path.as_os_str(),
+            input.path().as_os_str(),
+        ]);
+        assert!(!args.writes_to_stdout());
+        let files = vec![input.path().to_path_buf()];
+        let options = CountOptions::default();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+        let mut output = Output::open(&args)?;
+        assert!(!output.is_terminal());
+        assert!(!run(&args, &files, &options, &pool, &mut output)?);
+        let written: serde_json::Value = serde_json::from_slice(&std::fs::read(&path)?)?;
+        let (expected, _) = collect_stats(&files, &options, &Reporter::default());
+        assert_eq!(written, serde_json::to_value(&expected)?);
+
+        // 数え直した場合は前回の結果を残さずに書き直す
+        input.write_all("four\n".as_bytes())?;
+        run(&args, &files, &options, &pool, &mut output)?;
+        let written: Vec<Stats> = serde_json::from_slice(&std::fs::read(&path)?)?;
+        assert_eq!(written[0].counts.lines, 3);
+
+        // `-` は標準出力を表す
+        assert!(Args::parse_from(["text-stats", "--output", "-", "a"]).writes_to_stdout());
+        // 書き込めない場合は数える前にエラーになる
+        let args = Args::parse_from([
+            "text-stats".as_ref(),
+            "--output".as_ref(),
+            dir.path().join("missing/result.json").as_os_str(),
+            input.path().as_os_str(),
+        ]);
+        assert!(Output::open(&args).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run() -> io::Result<()> {
+        let mut valid = NamedTempFile::new()?;
+        valid.write_all("text\n".as_bytes())?;
+        let dir = tempfile::tempdir()?;
+        let missing = dir.path().join("missing.txt");
+        let files = vec![valid.path().to_path_buf(), missing.clone()];
+
+        let mut out = Vec::new();
+        assert!(dry_run(&mut out, &files, &CountOptions::default())?);
+        let expected = format!(
+            "OK {}\nERROR {}: no such file or directory\n",
+            valid.path().display(),
+            missing.display()
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        // 全て開ける場合は失敗にならない
+        let mut out = Vec::new();
+        assert!(!dry_run(&mut out, &files[..1], &CountOptions::default())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fail_on_empty() -> io::Result<()> {
+        let mut text = NamedTempFile::new()?;
+        text.write_all("text\n".as_bytes())?;
+        let empty = NamedTempFile::new()?;
+        let dir = tempfile::tempdir()?;
+        let result = dir.path().join("result.txt");
+        let run_with = |flags: &[&str], files: &[PathBuf]| -> io::Result<bool> {
+            let mut argv = vec!["text-stats".as_ref(), "--output".as_ref(), result.as_os_str()];
+            argv.extend(flags.iter().map(std::ffi::OsStr::new));
+            argv.push("a".as_ref());
+            let args = Args::parse_from(argv);
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+            run(&args, files, &CountOptions::default(), &pool, &mut Output::open(&args)?)
+        };
+        let both = [text.path().to_path_buf(), empty.path().to_path_buf()];
+
+        // 空のファイルがあれば失敗とし、結果は表示する
+        assert!(run_with(&["--fail-on-empty"], &both)?);
+        let written = std::fs::read_to_string(&result)?;
+        assert!(written.contains(&*empty.path().to_string_lossy()));
+        // `--skip-empty` で結果から除いた場合も失敗とする
+        assert!(run_with(&["--fail-on-empty", "--skip-empty"], &both)?);
+        // 空のファイルがない場合や、指定しない場合は失敗にならない
+        assert!(!run_with(&["--fail-on-empty"], &both[..1])?);
+        assert!(!run_with(&[], &both)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() -> io::Result<()> {
+        let mut before = NamedTempFile::new()?;
+        before.write_all("one two three\nfour\n".as_bytes())?;
+        let mut after = NamedTempFile::new()?;
+        after.write_all("one two\nthree\nfive\nsix\n".as_bytes())?;
+        let files = vec![before.path().to_path_buf(), after.path().to_path_buf()];
+        let (stats, _) = collect_stats(&files, &CountOptions::default(), &Reporter::default());
+
+        let mut out = Vec::new();
+        let args = Args::parse_from(["text-stats", "--compare", "a", "b"]);
+        assert!(!compare(&mut out, &stats, &args)?);
+        assert_eq!(String::from_utf8(out).unwrap(), "lines: +2, words: +1, chars: +4\n");
+
+        // JSONでは差を1つのオブジェクトとして出力する
+        let mut out = Vec::new();
+        let args = Args::parse_from(["text-stats", "--compare", "--json", "a", "b"]);
+        compare(&mut out, &stats, &args)?;
+        let diff: StatsDiff = serde_json::from_slice(&out)?;
+        assert_eq!((diff.lines, diff.words, diff.chars), (2, 1, 4));
+        assert_eq!(diff.from, before.path().to_string_lossy());
+
+        // 比較元と比較先を入れ替えると符号が逆になる
+        let (mut stats, _) = collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        stats.reverse();
+        let mut out = Vec::new();
+        let args = Args::parse_from(["text-stats", "--compare", "a", "b"]);
+        compare(&mut out, &stats, &args)?;
+        assert_eq!(String::from_utf8(out).unwrap(), "lines: -2, words: -1, chars: -4\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_output_unwrap_single() -> io::Result<()> {
+        let stat = |filename: &str| Stats {
+            filename: filename.to_string(),
+            counts: Counts::default(),
+        };
+        let json = |stats: &[Stats], args: &[&str]| -> io::Result<serde_json::Value> {
+            let mut out = Vec::new();
+            write_output(&mut out, stats, &Args::parse_from(args))?;
+            Ok(serde_json::from_slice(&out)?)
+        };
+
+        // 指定しない場合は入力が1つでも配列で出力する
+        let one = [stat("a")];
+        assert!(json(&one, &["text-stats", "--json", "a"])?.is_array());
+        // 入力が1つの場合は単一のオブジェクトになる
+        let value = json(&one, &["text-stats", "--json", "--unwrap-single", "a"])?;
+        assert_eq!(value["filename"], "a");
+        let value = json(&one, &["text-stats", "--json-compact", "--unwrap-single", "a"])?;
+        assert!(value.is_object());
+        // 複数の場合は配列のまま
+        let two = [stat("a"), stat("b")];
+        let value = json(&two, &["text-stats", "--json", "--unwrap-single", "a", "b"])?;
+        assert_eq!(value.as_array().map(Vec::len), Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_stats_lines_reverse() {
+        let args = Args::parse_from(["text-stats", "--sort", "lines", "--reverse", "a", "b"]);
+        let stat = |filename: &str, lines| Stats {
+            filename: filename.to_string(),
+            counts: Counts {
+                lines,
+                ..Default::default()
+            },
+        };
+        let mut stats = vec![stat("short.txt", 1), stat("long.txt", 10)];
+
+        sort_stats(&mut stats, args.sort.unwrap(), args.reverse);
+        let rows = with_total(stats, false);
+
+        let names: Vec<&str> = rows.iter().map(|stat| stat.filename.as_str()).collect();
+        assert_eq!(names, ["long.txt", "short.txt", "total"]);
+    }
+
+    #[test]
+    fn test_with_total_summary_only() -> io::Result<()> {
+        let mut first = NamedTempFile::new()?;
+        first.write_all("Hello, World!\n".as_bytes())?;
+        let mut second = NamedTempFile::new()?;
+        second.write_all("Line 1\nLine 2\n".as_bytes())?;
+        let files = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+
+        let (stats, _) =
+            collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        let rows = with_total(stats, false);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2].filename, "total");
+
+        let (stats, _) =
+            collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        let rows = with_total(stats, true);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].filename, "total");
+        assert_eq!(rows[0].counts.lines, 3);
+        assert_eq!(rows[0].counts.words, 6);
+
+        // JSONでは単一のオブジェクトとして出力される
+        let mut out = Vec::new();
+        output::write_json(&mut out, &rows[0])?;
+        let json: serde_json::Value = serde_json::from_slice(&out)?;
+        assert_eq!(json["lines"], 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_limit_before_sort() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut inputs = Vec::new();
+        for (name, lines) in [("1.txt", 5), ("2.txt", 1), ("3.txt", 9), ("4.txt", 2), ("5.txt", 7)] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "line\n".repeat(lines))?;
+            inputs.push(path);
+        }
+        let args = Args::parse_from(["text-stats", "--limit", "2", "--sort", "lines", "a"]);
+        let (mut files, _) = expand_paths(&[dir.path().join("*.txt")], None);
+        assert_eq!(files, inputs);
+        files.truncate(args.limit.unwrap().get());
+
+        // 展開した順の先頭2件のみを数え、その中で並べ替える
+        let (stats, had_error) = collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        assert!(!had_error);
+        let names: Vec<String> = prepare_stats(stats, &args)
+            .iter()
+            .map(|stat| Path::new(&stat.filename).file_name().unwrap().to_string_lossy().into())
+            .collect();
+        assert_eq!(names, ["2.txt", "1.txt", "total"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_paths_glob() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["a.txt", "b.txt", "c.md"] {
+            std::fs::write(dir.path().join(name), "text\n")?;
+        }
+
+        let pattern = dir.path().join("*.txt");
+        let (files, had_error) = expand_paths(&[pattern], None);
+        assert!(!had_error);
+        assert_eq!(files, vec![dir.path().join("a.txt"), dir.path().join("b.txt")]);
+
+        // メタ文字を含まないパスはそのまま渡される
+        let literal = vec![dir.path().join("missing.txt")];
+        let (files, had_error) = expand_paths(&literal, None);
+        assert!(!had_error);
+        assert_eq!(files, literal);
+
+        // URLはクエリ文字列に `?` を含んでもそのまま渡される
+        let url = vec![PathBuf::from("https://example.com/data.txt?raw=1")];
+        let (files, had_error) = expand_paths(&url, None);
+        assert!(!had_error);
+        assert_eq!(files, url);
+
+        // 何にも一致しないパターンはエラー
+        let (files, had_error) = expand_paths(&[dir.path().join("*.rs")], None);
+        assert!(had_error);
+        assert!(files.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_paths_recursive() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a.txt"), "a\n")?;
+        let paths = vec![dir.path().to_path_buf()];
+
+        // 再帰モードではディレクトリ内のファイルに展開される
+        let (files, had_error) = expand_paths(&paths, Some(&WalkOptions::default()));
+        assert!(!had_error);
+        assert_eq!(files, vec![dir.path().join("a.txt")]);
+
+        // 再帰モードでなければそのまま渡され、処理時にエラーになる
+        let (files, _) = expand_paths(&paths, None);
+        assert_eq!(files, paths);
+        let (stats, had_error) =
+            collect_stats(&files, &CountOptions::default(), &Reporter::default());
+        assert!(had_error);
+        assert!(stats.is_empty());
+
         Ok(())
     }
 }
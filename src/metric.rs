@@ -0,0 +1,88 @@
+//! 行ごとに値を集計する追加の項目
+//!
+//! `Metric` を実装した型を `CountOptions::metrics` に登録すると、集計した値が
+//! `Counts::metrics` に項目名をキーとして追加される。
+
+use serde_json::Value;
+
+/// 行ごとに値を集計する項目
+/// ファイルごとに `MetricFactory` で新しく作り、数える行ごとに `observe` を呼んだ後に `finish` で値を取り出す
+pub trait Metric {
+    /// 1行分（行末の区切りを含む）の内容を受け取る
+    /// `--filter` や `--every-nth` で数えない行は渡さない
+    fn observe(&mut self, line: &str);
+
+    /// 集計を終えて、項目名と値を返す
+    fn finish(self: Box<Self>) -> (String, Value);
+}
+
+/// ファイルごとに項目の集計器を作る関数
+pub type MetricFactory = fn() -> Box<dyn Metric>;
+
+/// 複数のファイルの項目の値を合計する関数
+/// 整数・小数はそれぞれ足し合わせ、それ以外の値は先のファイルの値を残す
+pub(crate) fn merge_value(total: &mut Value, other: &Value) {
+    if let (Some(a), Some(b)) = (total.as_u64(), other.as_u64()) {
+        *total = (a + b).into();
+    } else if let (Some(a), Some(b)) = (total.as_f64(), other.as_f64()) {
+        *total = (a + b).into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{count_stats_with_options, total_stats, CountOptions, Stats};
+    use std::io;
+
+    /// `TODO` を含む行の数を数える項目
+    #[derive(Default)]
+    struct TodoLines {
+        count: usize,
+    }
+
+    impl Metric for TodoLines {
+        fn observe(&mut self, line: &str) {
+            if line.contains("TODO") {
+                self.count += 1;
+            }
+        }
+
+        fn finish(self: Box<Self>) -> (String, Value) {
+            ("todo_lines".to_string(), self.count.into())
+        }
+    }
+
+    #[test]
+    fn test_custom_metric() -> io::Result<()> {
+        let options = CountOptions {
+            metrics: vec![|| Box::new(TodoLines::default())],
+            ..Default::default()
+        };
+        let text = "fn main() {\n    // TODO: handle errors\n}\n// TODO later\n";
+        let counts = count_stats_with_options(text.as_bytes(), &options)?;
+        assert_eq!(counts.metrics["todo_lines"], 2);
+
+        // 合計ではファイルごとの値を足し合わせる
+        let other = count_stats_with_options("TODO\n".as_bytes(), &options)?;
+        let total = total_stats(&[
+            Stats { filename: "a.rs".to_string(), counts },
+            Stats { filename: "b.rs".to_string(), counts: other },
+        ]);
+        assert_eq!(total.counts.metrics["todo_lines"], 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_value() {
+        let mut total = Value::from(2);
+        merge_value(&mut total, &Value::from(3));
+        assert_eq!(total, 5);
+        merge_value(&mut total, &Value::from(0.5));
+        assert_eq!(total, 5.5);
+        let mut text = Value::from("first");
+        merge_value(&mut text, &Value::from("second"));
+        assert_eq!(text, "first");
+    }
+}
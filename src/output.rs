@@ -0,0 +1,967 @@
+//! 統計情報を各出力形式で書き出す処理
+
+use crate::{Args, Field, Template, TemplatePart};
+use num_format::{Locale, ToFormattedString};
+use owo_colors::{OwoColorize, Style};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use text_stats::{line_length_histogram, top_words, total_stats, Counts, Stats, StatsDiff};
+use unicode_width::UnicodeWidthStr;
+
+/// JSON形式で出力する関数
+pub fn write_json<W: Write, T: Serialize + ?Sized>(out: &mut W, value: &T) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *out, value)?;
+    writeln!(out)
+}
+
+/// 改行や字下げを含まない1行のJSON形式で出力する関数
+pub fn write_json_compact<W: Write, T: Serialize + ?Sized>(
+    out: &mut W,
+    value: &T,
+) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, value)?;
+    writeln!(out)
+}
+
+/// JSON出力の各ファイルの統計情報のJSON Schemaを出力する関数
+/// `Stats` の定義から生成するため、項目を追加しても自動的に反映される
+pub fn write_schema<W: Write>(out: &mut W) -> io::Result<()> {
+    write_json(out, &schemars::schema_for!(Stats))
+}
+
+/// ファイルごとの結果と合計をまとめたJSONの最上位のオブジェクト（`--summary-json` 用）
+#[derive(Serialize)]
+struct SummaryJson<'a> {
+    files: &'a [Stats],  // ファイルごとの統計情報（合計行を含まない）
+    total: &'a Counts,   // 全てのファイルの合計
+}
+
+/// ファイルごとの結果と合計を1つのオブジェクトにまとめたJSON形式で出力する関数
+/// `stats` は合計行を含まないファイルごとの結果とし、合計はここで計算する
+pub fn write_summary_json<W: Write>(out: &mut W, stats: &[Stats]) -> io::Result<()> {
+    let total = total_stats(stats);
+    write_json(out, &SummaryJson { files: stats, total: &total.counts })
+}
+
+/// JSON Lines形式で出力する関数
+/// 配列で囲まず、1ファイルにつき1行のJSONオブジェクトを入力と同じ順序で出力する
+pub fn write_ndjson<W: Write>(out: &mut W, stats: &[Stats]) -> io::Result<()> {
+    for stat in stats {
+        serde_json::to_writer(&mut *out, stat)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// YAML形式で出力する関数
+/// 統計情報の一覧はJSONと同じキーを持つマッピングのシーケンスとして出力する
+pub fn write_yaml<W: Write, T: Serialize + ?Sized>(out: &mut W, value: &T) -> io::Result<()> {
+    serde_yaml::to_writer(out, value).map_err(io::Error::other)
+}
+
+/// TOMLの最上位の表（TOML出力用）
+#[derive(Serialize)]
+struct TomlFiles<'a> {
+    files: &'a [Stats],
+}
+
+/// TOML形式で出力する関数
+/// TOMLの最上位は表である必要があるため、統計情報の一覧を `[[files]]` の表の配列として出力する
+pub fn write_toml<W: Write>(out: &mut W, stats: &[Stats]) -> io::Result<()> {
+    let toml = toml::to_string(&TomlFiles { files: stats }).map_err(io::Error::other)?;
+    out.write_all(toml.as_bytes())
+}
+
+/// 単語の出現回数（JSON出力用）
+#[derive(Serialize)]
+struct WordCount {
+    word: String,
+    count: usize,
+}
+
+/// ファイルごとの出現回数上位の単語（JSON出力用）
+#[derive(Serialize)]
+struct TopWords<'a> {
+    filename: &'a str,
+    top_words: Vec<WordCount>,
+}
+
+/// 出現回数の多い単語を上位 `n` 件ずつ出力する関数
+pub fn write_top_words<W: Write>(out: &mut W, stats: &[Stats], n: usize) -> io::Result<()> {
+    for stat in stats {
+        writeln!(out, "{}:", stat.filename)?;
+        for (word, count) in top_words(&stat.counts.word_frequencies, n) {
+            writeln!(out, "{:>12} {}", count, word)?;
+        }
+    }
+    Ok(())
+}
+
+/// 出現回数の多い単語を上位 `n` 件ずつJSON形式で出力する関数
+pub fn write_top_words_json<W: Write>(out: &mut W, stats: &[Stats], n: usize) -> io::Result<()> {
+    let results: Vec<TopWords> = stats
+        .iter()
+        .map(|stat| TopWords {
+            filename: &stat.filename,
+            top_words: top_words(&stat.counts.word_frequencies, n)
+                .into_iter()
+                .map(|(word, count)| WordCount { word, count })
+                .collect(),
+        })
+        .collect();
+    serde_json::to_writer_pretty(&mut *out, &results)?;
+    writeln!(out)
+}
+
+/// 2つのファイルの差を `lines: +3, words: -2, chars: +40` の形式で出力する関数
+pub fn write_diff<W: Write>(out: &mut W, diff: &StatsDiff) -> io::Result<()> {
+    writeln!(
+        out,
+        "lines: {:+}, words: {:+}, chars: {:+}",
+        diff.lines, diff.words, diff.chars
+    )
+}
+
+/// 行の文字数のヒストグラムをファイルごとに棒グラフで出力する関数
+/// 棒の長さは最も行数の多い範囲が `width` 列に収まるように縮める
+pub fn write_histogram<W: Write>(
+    out: &mut W,
+    stats: &[Stats],
+    bucket_size: usize,
+    width: usize,
+) -> io::Result<()> {
+    for stat in stats {
+        writeln!(out, "{}:", stat.filename)?;
+        let buckets = line_length_histogram(&stat.counts.line_lengths, bucket_size);
+        let labels: Vec<String> = (0..buckets.len())
+            .map(|i| {
+                let start = if i == 0 { 0 } else { i * bucket_size + 1 };
+                format!("{}-{}", start, (i + 1) * bucket_size)
+            })
+            .collect();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+        let max_count = buckets.iter().copied().max().unwrap_or(0);
+        let count_width = max_count.to_string().len();
+        // ラベル・区切り・行数を除いた残りを棒に使う（狭すぎる端末でも最低10列）
+        let bar_width = width.saturating_sub(label_width + count_width + 4).max(10);
+
+        for (label, &count) in labels.iter().zip(&buckets) {
+            // 1行でもある範囲は最低1列の棒を表示する
+            let bar = if count == 0 {
+                0
+            } else {
+                (count * bar_width / max_count).max(1)
+            };
+            writeln!(
+                out,
+                "{:>label_width$} | {:<bar_width$} {:>count_width$}",
+                label,
+                "#".repeat(bar),
+                count
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// CSV形式で出力する関数
+/// 1行目にヘッダー行、以降に1ファイル1行で出力する
+pub fn write_csv<W: Write>(out: &mut W, stats: &[Stats]) -> io::Result<()> {
+    writeln!(out, "filename,lines,words,chars,bytes")?;
+    for stat in stats {
+        let counts = &stat.counts;
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            csv_escape(&stat.filename),
+            counts.lines,
+            counts.words,
+            counts.chars,
+            counts.bytes
+        )?;
+    }
+    Ok(())
+}
+
+/// CSVのフィールドをRFC 4180に従ってエスケープする関数
+/// カンマ・ダブルクォート・改行を含む場合はダブルクォートで囲み、
+/// 内部のダブルクォートは二重にする
+fn csv_escape(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// `--output-template` の書式で1ファイル1行ずつ出力する関数
+pub fn write_template<W: Write>(
+    out: &mut W,
+    stats: &[Stats],
+    template: &Template,
+) -> io::Result<()> {
+    for stat in stats {
+        for part in &template.0 {
+            match part {
+                TemplatePart::Text(text) => write!(out, "{}", text)?,
+                TemplatePart::Filename => write!(out, "{}", stat.filename)?,
+                TemplatePart::Field(field) => write!(out, "{}", field.value(&stat.counts))?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Markdownの表形式で出力する関数
+/// `--fields` または `-l` `-w` `-m` `-c` `-L` が指定された場合はその列のみ、指定がなければ通常の4列を出力する
+pub fn write_markdown<W: Write>(out: &mut W, stats: &[Stats], args: &Args) -> io::Result<()> {
+    let columns = match &args.fields {
+        Some(fields) => fields.clone(),
+        None => {
+            let selected = [args.lines, args.words, args.chars, args.bytes, args.max_line_length];
+            let any_selected = selected.contains(&true);
+            [Field::Lines, Field::Words, Field::Chars, Field::Bytes, Field::MaxLineLength]
+                .into_iter()
+                .zip(selected)
+                // 指定がない場合は最大行長を除く通常の4列
+                .filter(|&(field, selected)| {
+                    selected || (!any_selected && field != Field::MaxLineLength)
+                })
+                .map(|(field, _)| field)
+                .collect()
+        }
+    };
+
+    let mut header = String::from("| FILE |");
+    let mut separator = String::from("|---|");
+    for field in &columns {
+        header.push_str(&format!(" {} |", field.header()));
+        separator.push_str("---:|");
+    }
+    writeln!(out, "{}", header)?;
+    writeln!(out, "{}", separator)?;
+    for stat in stats {
+        let mut row = format!("| {} |", markdown_escape(&stat.filename));
+        for field in &columns {
+            row.push_str(&format!(" {} |", field.value(&stat.counts)));
+        }
+        writeln!(out, "{}", row)?;
+    }
+    Ok(())
+}
+
+/// Markdownの表のセルで区切りと解釈されないよう `|` を `\|` にエスケープする関数
+fn markdown_escape(cell: &str) -> Cow<'_, str> {
+    if cell.contains('|') {
+        Cow::Owned(cell.replace('|', "\\|"))
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
+
+/// テーブル形式で出力する関数
+/// 項目を1つだけ指定された場合は `ファイル名: 値` の形式で出力する
+pub fn write_table<W: Write>(out: &mut W, stats: &[Stats], args: &Args) -> io::Result<()> {
+    // 桁区切りは人が読むテーブル形式のみで使い、JSONやCSVでは付けない
+    let number = |n: usize| {
+        if args.thousands_sep {
+            n.to_formatted_string(&Locale::en)
+        } else {
+            n.to_string()
+        }
+    };
+
+    // 表示する項目を指定された場合はその列のみを指定された順に表示する
+    if let Some(fields) = &args.fields {
+        let mut header = vec!["FILE"];
+        header.extend(fields.iter().map(|field| field.header()));
+        let rows: Vec<Vec<String>> = stats
+            .iter()
+            .map(|stat| {
+                let mut row = vec![stat.filename.clone()];
+                row.extend(fields.iter().map(|field| number(field.value(&stat.counts))));
+                row
+            })
+            .collect();
+        return write_aligned(out, &header, &rows, &row_styles(stats, args), !args.no_header);
+    }
+
+    // 項目が1つだけ指定された場合はヘッダーを表示しない
+    let single = args.lines || args.words || args.chars || args.bytes || args.max_line_length;
+    if single {
+        for stat in stats {
+            let counts = &stat.counts;
+            if args.lines {
+                writeln!(out, "{}: {} lines", stat.filename, number(counts.lines))?;
+            } else if args.words {
+                writeln!(out, "{}: {} words", stat.filename, number(counts.words))?;
+            } else if args.chars {
+                writeln!(out, "{}: {} chars", stat.filename, number(counts.chars))?;
+            } else if args.bytes {
+                writeln!(out, "{}: {} bytes", stat.filename, number(counts.bytes))?;
+            } else {
+                writeln!(out, "{}: {}", stat.filename, number(counts.max_line_length))?;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut header = vec!["FILE", "LINES", "WORDS", "CHARS", "BYTES"];
+    if args.chars_no_spaces {
+        header.push("NO-SPACE");
+    }
+    if args.graphemes {
+        header.push("GRAPHEMES");
+    }
+    if args.width {
+        header.push("WIDTH");
+    }
+    if args.match_pattern.is_some() {
+        header.push("MATCHES");
+    }
+    if args.count_lines_matching.is_some() {
+        header.push("MATCHING LINES");
+    }
+    if args.unique_words {
+        header.push("UNIQUE");
+    }
+    if args.palindromes {
+        header.push("PALINDROMES");
+    }
+    if args.indentation {
+        header.extend(["TAB INDENT", "SPACE INDENT", "MIXED INDENT"]);
+    }
+    if args.sentences {
+        header.push("SENTENCES");
+    }
+    if args.paragraphs {
+        header.push("PARAGRAPHS");
+    }
+    if args.word_lengths {
+        header.extend(["LONGEST", "SHORTEST", "AVG LEN"]);
+    }
+    if args.word_length_dist {
+        header.push("WORD LENGTHS");
+    }
+    if args.classify {
+        header.extend(["LETTERS", "DIGITS", "SPACES", "PUNCT"]);
+    }
+    // 制御文字なども見分けられるよう、列名は `','` `'\t'` のようにエスケープして表示する
+    let count_chars = args.char_set();
+    let char_labels: Vec<String> = count_chars.iter().map(|c| format!("{c:?}")).collect();
+    header.extend(char_labels.iter().map(String::as_str));
+    if args.ascii_only {
+        header.extend(["ASCII", "NON-ASCII"]);
+    }
+    if args.averages {
+        header.extend(["WORDS/LINE", "CHARS/WORD"]);
+    }
+    if args.line_stats {
+        header.extend(["MIN LINE", "MAX LINE", "AVG LINE"]);
+    }
+    if args.blank_lines {
+        header.extend(["EMPTY", "BLANK"]);
+    }
+    if args.trailing_whitespace {
+        header.push("TRAILING WS");
+    }
+    if args.line_endings {
+        header.extend(["CRLF", "LF", "CR"]);
+    }
+    if args.every_nth.is_some() {
+        header.push("SAMPLE");
+    }
+    if args.hash.is_some() {
+        header.push("HASH");
+    }
+
+    let rows: Vec<Vec<String>> = stats
+        .iter()
+        .map(|stat| {
+            let counts = &stat.counts;
+            let mut row = vec![
+                stat.filename.clone(),
+                number(counts.lines),
+                number(counts.words),
+                number(counts.chars),
+                number(counts.bytes),
+            ];
+            if args.chars_no_spaces {
+                row.push(number(counts.chars_no_spaces));
+            }
+            if args.graphemes {
+                row.push(number(counts.graphemes.unwrap_or(0)));
+            }
+            if args.width {
+                row.push(number(counts.display_width.unwrap_or(0)));
+            }
+            if args.match_pattern.is_some() {
+                row.push(number(counts.matches.unwrap_or(0)));
+            }
+            if args.count_lines_matching.is_some() {
+                row.push(number(counts.matching_lines.unwrap_or(0)));
+            }
+            if args.unique_words {
+                row.push(number(counts.unique_words.unwrap_or(0)));
+            }
+            if args.palindromes {
+                row.push(number(counts.palindrome_lines.unwrap_or(0)));
+            }
+            if args.indentation {
+                row.push(number(counts.tab_indented.unwrap_or(0)));
+                row.push(number(counts.space_indented.unwrap_or(0)));
+                row.push(number(counts.mixed_indent_lines.unwrap_or(0)));
+            }
+            if args.sentences {
+                row.push(number(counts.sentences));
+            }
+            if args.paragraphs {
+                row.push(number(counts.paragraphs));
+            }
+            if args.word_lengths {
+                row.extend([
+                    counts.longest_word.clone().unwrap_or_default(),
+                    counts.shortest_word.clone().unwrap_or_default(),
+                    format!("{:.2}", counts.avg_word_length.unwrap_or(0.0)),
+                ]);
+            }
+            if args.word_length_dist {
+                // 文字数の昇順に `文字数:単語数` を空白区切りで並べる
+                let dist = counts.word_length_counts.iter().flatten();
+                let cells: Vec<String> = dist.map(|(len, n)| format!("{}:{}", len, n)).collect();
+                row.push(cells.join(" "));
+            }
+            if args.classify {
+                row.extend([
+                    number(counts.letters),
+                    number(counts.digits),
+                    number(counts.whitespace),
+                    number(counts.punctuation),
+                ]);
+            }
+            for c in &count_chars {
+                let count = counts.char_counts.as_ref().and_then(|map| map.get(c));
+                row.push(number(count.copied().unwrap_or(0)));
+            }
+            if args.ascii_only {
+                let is_ascii = if counts.non_ascii == 0 { "yes" } else { "no" };
+                row.extend([is_ascii.to_string(), number(counts.non_ascii)]);
+            }
+            if args.averages {
+                row.extend([
+                    format!("{:.2}", counts.avg_words_per_line),
+                    format!("{:.2}", counts.avg_chars_per_word),
+                ]);
+            }
+            if args.line_stats {
+                row.extend([
+                    number(counts.min_line_length),
+                    number(counts.max_line_length),
+                    format!("{:.2}", counts.avg_line_length),
+                ]);
+            }
+            if args.blank_lines {
+                row.extend([number(counts.empty_lines), number(counts.blank_lines)]);
+            }
+            if args.trailing_whitespace {
+                row.push(number(counts.trailing_ws_lines));
+            }
+            if args.line_endings {
+                row.extend(
+                    [counts.crlf, counts.lf, counts.cr].map(|n| number(n.unwrap_or(0))),
+                );
+            }
+            if let Some(n) = counts.sample_every.filter(|_| args.every_nth.is_some()) {
+                // 標本のみの件数か、N倍した推定値かを示す
+                let estimated = if counts.extrapolated == Some(true) { " est." } else { "" };
+                row.push(format!("1/{}{}", n, estimated));
+            }
+            if args.hash.is_some() {
+                row.push(counts.hash.clone().unwrap_or_default());
+            }
+            row
+        })
+        .collect();
+
+    write_aligned(out, &header, &rows, &row_styles(stats, args), !args.no_header)
+}
+
+/// テーブルの各行を表示する色を求める関数
+/// 最も行数の多いファイルを緑、空のファイルを赤にする。色を付けない場合は全て `None` になる
+///
+/// 複数ファイルの場合の末尾は合計行のため、最も行数の多いファイルの判定には含めず色も付けない。
+fn row_styles(stats: &[Stats], args: &Args) -> Vec<Option<Style>> {
+    if !args.use_color() {
+        return vec![None; stats.len()];
+    }
+    let files = if stats.len() > 1 { &stats[..stats.len() - 1] } else { stats };
+    // 1ファイルのみの場合は比べる対象がないため、最も行数が多いとはみなさない
+    let most_lines = files
+        .iter()
+        .map(|stat| stat.counts.lines)
+        .max()
+        .filter(|&lines| lines > 0 && files.len() > 1);
+    let mut styles: Vec<Option<Style>> = files
+        .iter()
+        .map(|stat| {
+            if stat.counts.is_empty() {
+                Some(Style::new().red())
+            } else if Some(stat.counts.lines) == most_lines {
+                Some(Style::new().green())
+            } else {
+                None
+            }
+        })
+        .collect();
+    styles.resize(stats.len(), None);
+    styles
+}
+
+/// ヘッダーと各行を、列ごとに最も長い値の幅に揃えて出力する関数
+/// 先頭の列（ファイル名）は左寄せ、それ以外の列（数値）は右寄せにする
+/// `show_header` が偽の場合はヘッダーと区切り線を出力しない（列の幅は変えない）
+/// `styles` に色を指定した行は、揃えた後の行全体に色を付ける
+fn write_aligned<W: Write>(
+    out: &mut W,
+    header: &[&str],
+    rows: &[Vec<String>],
+    styles: &[Option<Style>],
+    show_header: bool,
+) -> io::Result<()> {
+    // 全角文字を含むファイル名も揃うよう、文字数ではなく表示幅で測る
+    let mut widths: Vec<usize> = header.iter().map(|name| name.width()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.width());
+        }
+    }
+
+    let write_cells = |out: &mut W, cells: &[&str], style: Option<Style>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, &width))| {
+                let padding = " ".repeat(width - cell.width());
+                if i == 0 {
+                    format!("{}{}", cell, padding)
+                } else {
+                    format!("{}{}", padding, cell)
+                }
+            })
+            .collect();
+        let line = line.join(" ");
+        match style {
+            Some(style) => writeln!(out, "{}", line.trim_end().style(style)),
+            None => writeln!(out, "{}", line.trim_end()),
+        }
+    };
+
+    if show_header {
+        write_cells(out, header, None)?;
+        let separator: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+        write_cells(out, &separator.iter().map(String::as_str).collect::<Vec<_>>(), None)?;
+    }
+    for (row, &style) in rows.iter().zip(styles) {
+        write_cells(out, &row.iter().map(String::as_str).collect::<Vec<_>>(), style)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::io::IsTerminal;
+
+    fn sample_stats(filename: &str) -> Stats {
+        Stats {
+            filename: filename.to_string(),
+            counts: Counts {
+                lines: 1,
+                words: 2,
+                chars: 14,
+                bytes: 14,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("plain.txt"), "plain.txt");
+        assert_eq!(csv_escape("a,b.txt"), "\"a,b.txt\"");
+        assert_eq!(csv_escape("say \"hi\".txt"), "\"say \"\"hi\"\".txt\"");
+    }
+
+    #[test]
+    fn test_write_csv() -> io::Result<()> {
+        let mut out = Vec::new();
+        write_csv(&mut out, &[sample_stats("a,b.txt")])?;
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "filename,lines,words,chars,bytes\n\"a,b.txt\",1,2,14,14\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_template() -> io::Result<()> {
+        let stats = vec![sample_stats("a.txt"), sample_stats("dir/b c.txt")];
+        let template = "{filename}\t{lines} {words} {chars} [{max-line-length}]";
+        let template = crate::parse_template(template).unwrap();
+
+        let mut out = Vec::new();
+        write_template(&mut out, &stats, &template)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a.txt\t1 2 14 [0]\ndir/b c.txt\t1 2 14 [0]\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_markdown() -> io::Result<()> {
+        let args = Args::parse_from(["text-stats", "--markdown", "a.txt"]);
+        let mut out = Vec::new();
+        write_markdown(&mut out, &[sample_stats("a|b.txt")], &args)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "| FILE | LINES | WORDS | CHARS | BYTES |\n\
+             |---|---:|---:|---:|---:|\n\
+             | a\\|b.txt | 1 | 2 | 14 | 14 |\n"
+        );
+
+        // 指定された列のみを出力する
+        let args = Args::parse_from(["text-stats", "--markdown", "-l", "-w", "a.txt"]);
+        let mut out = Vec::new();
+        write_markdown(&mut out, &[sample_stats("a.txt")], &args)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "| FILE | LINES | WORDS |\n|---|---:|---:|\n| a.txt | 1 | 2 |\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_table_fields() -> io::Result<()> {
+        // 指定した順に列を表示し、個別の項目の指定より優先する
+        let args = Args::parse_from(["text-stats", "--fields", "words,lines", "-c", "a.txt"]);
+        let mut out = Vec::new();
+        write_table(&mut out, &[sample_stats("a.txt")], &args)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "FILE  WORDS LINES\n----- ----- -----\na.txt     2     1\n"
+        );
+
+        let args = Args::parse_from(["text-stats", "--markdown", "--fields", "bytes,lines", "a"]);
+        let mut out = Vec::new();
+        write_markdown(&mut out, &[sample_stats("a.txt")], &args)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "| FILE | BYTES | LINES |\n|---|---:|---:|\n| a.txt | 14 | 1 |\n"
+        );
+
+        // 不明な項目名は指定できる項目名とともにエラーにする
+        let err = Args::try_parse_from(["text-stats", "--fields", "lines,colors", "a"])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidValue);
+        assert!(err.to_string().contains("possible values: lines, words, chars, bytes"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_table_color() -> io::Result<()> {
+        let mut longer = sample_stats("long.txt");
+        longer.counts.lines = 5;
+        let empty = Stats {
+            filename: "empty.txt".to_string(),
+            counts: Counts::default(),
+        };
+        let stats = [sample_stats("a.txt"), longer, empty, sample_stats("total")];
+        let table = |color: &str| -> io::Result<String> {
+            let args = Args::parse_from(["text-stats", "--color", color, "a"]);
+            let mut out = Vec::new();
+            write_table(&mut out, &stats, &args)?;
+            Ok(String::from_utf8(out).unwrap())
+        };
+
+        // `never` ではANSIエスケープシーケンスを含まない
+        let plain = table("never")?;
+        assert!(!plain.contains('\x1b'));
+
+        // `always` では最も行数の多いファイルを緑、空のファイルを赤で表示し、合計行には色を付けない
+        let colored = table("always")?;
+        let lines: Vec<&str> = colored.lines().collect();
+        let plain_lines: Vec<&str> = plain.lines().collect();
+        assert_eq!(lines[2], plain_lines[2]);
+        assert_eq!(lines[3], format!("\x1b[32m{}\x1b[0m", plain_lines[3]));
+        assert_eq!(lines[4], format!("\x1b[31m{}\x1b[0m", plain_lines[4]));
+        assert_eq!(lines[5], plain_lines[5]);
+
+        // 出力先が端末でない場合は `auto` でも色を付けない
+        if !io::stdout().is_terminal() {
+            assert_eq!(table("auto")?, plain);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_schema() -> io::Result<()> {
+        let mut out = Vec::new();
+        write_schema(&mut out)?;
+        let schema: serde_json::Value = serde_json::from_slice(&out)?;
+
+        let properties = schema["properties"].as_object().unwrap();
+        for name in ["filename", "lines", "words", "chars"] {
+            assert!(properties.contains_key(name), "missing {name}");
+        }
+        // 集計用の内部的な項目は含まない
+        assert!(!properties.contains_key("word_frequencies"));
+        let required = schema["required"].as_array().unwrap();
+        assert!(required.contains(&"lines".into()));
+        assert!(!required.contains(&"graphemes".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_summary_json() -> io::Result<()> {
+        let mut stats = vec![sample_stats("a.txt"), sample_stats("b.txt")];
+        stats[1].counts.lines = 4;
+
+        let mut out = Vec::new();
+        write_summary_json(&mut out, &stats)?;
+        let output: serde_json::Value = serde_json::from_slice(&out)?;
+
+        let object = output.as_object().unwrap();
+        assert_eq!(object.keys().collect::<Vec<_>>(), ["files", "total"]);
+        let files = output["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["filename"], "a.txt");
+        // 合計はファイルとして扱わないため、ファイル名を持たない
+        assert!(output["total"].get("filename").is_none());
+        let lines: u64 = files.iter().map(|file| file["lines"].as_u64().unwrap()).sum();
+        assert_eq!(output["total"]["lines"], lines);
+        assert_eq!(output["total"]["words"], 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_json_compact() -> io::Result<()> {
+        let stats = vec![sample_stats("a.txt"), sample_stats("b.txt")];
+
+        let mut out = Vec::new();
+        write_json_compact(&mut out, &stats)?;
+        let output = String::from_utf8(out).unwrap();
+
+        // 末尾の改行以外に改行を含まない
+        assert_eq!(output.matches('\n').count(), 1);
+        assert!(output.ends_with("}]\n"));
+        let parsed: Vec<Stats> = serde_json::from_str(&output)?;
+        assert_eq!(parsed, stats);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ndjson() -> io::Result<()> {
+        let stats = vec![sample_stats("a.txt"), sample_stats("b.txt")];
+
+        let mut out = Vec::new();
+        write_ndjson(&mut out, &stats)?;
+        let output = String::from_utf8(out).unwrap();
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for (line, stat) in lines.iter().zip(&stats) {
+            let parsed: Stats = serde_json::from_str(line)?;
+            assert_eq!(&parsed, stat);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_yaml_round_trip() -> io::Result<()> {
+        let mut stat = sample_stats("a.txt");
+        stat.counts.graphemes = Some(13);
+        let stats = vec![stat, sample_stats("b: c.txt")];
+
+        let mut out = Vec::new();
+        write_yaml(&mut out, &stats)?;
+        let yaml = String::from_utf8(out).unwrap();
+
+        assert!(yaml.starts_with("- filename: a.txt\n  lines: 1\n"));
+        let parsed: Vec<Stats> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, stats);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_toml_round_trip() -> io::Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Files {
+            files: Vec<Stats>,
+        }
+
+        let mut stat = sample_stats("a.txt");
+        stat.counts.matches = Some(2);
+        let stats = vec![stat, sample_stats("b \"c\".txt")];
+
+        let mut out = Vec::new();
+        write_toml(&mut out, &stats)?;
+        let toml = String::from_utf8(out).unwrap();
+
+        assert!(toml.starts_with("[[files]]\nfilename = \"a.txt\"\nlines = 1\n"));
+        let parsed: Files = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed.files, stats);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_histogram() -> io::Result<()> {
+        let mut stat = sample_stats("a.txt");
+        stat.counts.line_lengths = vec![0, 5, 5, 5, 12, 25];
+
+        let mut out = Vec::new();
+        write_histogram(&mut out, &[stat], 10, 30)?;
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a.txt:\n\
+             \x200-10 | #################### 4\n\
+             11-20 | #####                1\n\
+             21-30 | #####                1\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_top_words() -> io::Result<()> {
+        let mut stat = sample_stats("a.txt");
+        stat.counts.word_frequencies = [("world", 3), ("hello", 3), ("big", 1)]
+            .into_iter()
+            .map(|(word, count)| (word.to_string(), count))
+            .collect();
+
+        let mut out = Vec::new();
+        write_top_words(&mut out, &[stat], 2)?;
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a.txt:\n           3 hello\n           3 world\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_table_blank_lines_columns() -> io::Result<()> {
+        let mut stat = sample_stats("a.txt");
+        stat.counts.empty_lines = 3;
+        stat.counts.blank_lines = 4;
+        let stats = [stat];
+
+        // フラグがなければ空行の列は表示しない
+        let mut out = Vec::new();
+        write_table(&mut out, &stats, &Args::parse_from(["text-stats", "a.txt"]))?;
+        assert!(!String::from_utf8(out).unwrap().contains("EMPTY"));
+
+        let mut out = Vec::new();
+        let args = Args::parse_from(["text-stats", "--blank-lines", "a.txt"]);
+        write_table(&mut out, &stats, &args)?;
+        let output = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].ends_with("EMPTY BLANK"));
+        assert!(lines[2].ends_with("    3     4"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_table_sizes_columns_to_content() -> io::Result<()> {
+        let mut long = sample_stats("very/long/path/to/file.txt");
+        long.counts.chars = 1234567;
+        let stats = [sample_stats("a.txt"), long];
+
+        let mut out = Vec::new();
+        write_table(&mut out, &stats, &Args::parse_from(["text-stats", "a.txt"]))?;
+        let output = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            output,
+            "\
+FILE                       LINES WORDS   CHARS BYTES
+-------------------------- ----- ----- ------- -----
+a.txt                          1     2      14    14
+very/long/path/to/file.txt     1     2 1234567    14
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_table_no_header() -> io::Result<()> {
+        let stats = [sample_stats("a.txt")];
+
+        let mut out = Vec::new();
+        write_table(&mut out, &stats, &Args::parse_from(["text-stats", "a.txt"]))?;
+        let with_header = String::from_utf8(out).unwrap();
+        assert_eq!(
+            with_header,
+            "FILE  LINES WORDS CHARS BYTES\n\
+             ----- ----- ----- ----- -----\n\
+             a.txt     1     2    14    14\n"
+        );
+
+        let mut out = Vec::new();
+        let args = Args::parse_from(["text-stats", "--no-header", "a.txt"]);
+        write_table(&mut out, &stats, &args)?;
+        let without_header = String::from_utf8(out).unwrap();
+        assert_eq!(without_header, "a.txt     1     2    14    14\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_table_thousands_sep() -> io::Result<()> {
+        let mut stat = sample_stats("a.txt");
+        stat.counts.chars = 1234567;
+        let stats = [stat];
+
+        let mut out = Vec::new();
+        let args = Args::parse_from(["text-stats", "--thousands-sep", "a.txt"]);
+        write_table(&mut out, &stats, &args)?;
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.lines().nth(2).unwrap().ends_with("1,234,567    14"));
+
+        let mut out = Vec::new();
+        let args = Args::parse_from(["text-stats", "--thousands-sep", "-m", "a.txt"]);
+        write_table(&mut out, &stats, &args)?;
+        assert_eq!(String::from_utf8(out).unwrap(), "a.txt: 1,234,567 chars\n");
+
+        // JSONでは区切らずに数値のまま出力する
+        let mut out = Vec::new();
+        write_json(&mut out, &stats)?;
+        let json: serde_json::Value = serde_json::from_slice(&out)?;
+        assert_eq!(json[0]["chars"], 1234567);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+//! 入力ファイルの変更を監視する処理
+
+use notify::event::{AccessKind, AccessMode, EventKind, MetadataKind, ModifyKind};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// 1回の保存で続けて発生するイベントをまとめる間隔
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// 入力ファイルの変更を監視する構造体
+///
+/// エディタは上書きではなく一時ファイルの名前を変更して保存することがあるため、
+/// ファイルそのものではなく親ディレクトリを監視し、対象のファイルのイベントのみを拾う。
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,  // 破棄すると監視が止まるため保持しておく
+    events: Receiver<notify::Result<Event>>,
+    files: HashSet<PathBuf>,  // 監視対象のファイル（正規化したパス）
+}
+
+impl FileWatcher {
+    /// ファイルの一覧の監視を始める関数
+    pub fn new(files: &[PathBuf]) -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        let files: HashSet<PathBuf> = files
+            .iter()
+            .map(fs::canonicalize)
+            .collect::<Result<_, _>>()?;
+        let dirs: HashSet<PathBuf> = files
+            .iter()
+            .filter_map(|file| file.parent().map(PathBuf::from))
+            .collect();
+        for dir in &dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(FileWatcher {
+            _watcher: watcher,
+            events,
+            files,
+        })
+    }
+
+    /// 監視しているファイルのいずれかが変更されるまで待つ関数
+    /// 変更後 `DEBOUNCE` の間に続いたイベントはまとめて1回の変更とする
+    ///
+    /// # 戻り値
+    /// * 変更された場合は `true`、`timeout` を指定して時間内に変更がなかった場合は `false`
+    pub fn wait(&self, timeout: Option<Duration>) -> notify::Result<bool> {
+        loop {
+            let event = match timeout {
+                Some(timeout) => match self.events.recv_timeout(timeout) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => return Ok(false),
+                    Err(RecvTimeoutError::Disconnected) => return Err(disconnected()),
+                },
+                None => self.events.recv().map_err(|_| disconnected())?,
+            };
+            if self.is_change(&event?) {
+                break;
+            }
+        }
+
+        while let Ok(event) = self.events.recv_timeout(DEBOUNCE) {
+            event?;
+        }
+        Ok(true)
+    }
+
+    /// 監視対象のファイルの内容が変わった可能性のあるイベントかどうかを判定する関数
+    /// 数え直すときの読み込みで発生するアクセスのイベントは無視する
+    fn is_change(&self, event: &Event) -> bool {
+        let is_change = match event.kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::AccessTime)) => false,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+            _ => false,
+        };
+        is_change && event.paths.iter().any(|path| self.files.contains(path))
+    }
+}
+
+/// 監視が止まった場合のエラー
+fn disconnected() -> notify::Error {
+    notify::Error::generic("file watcher stopped unexpectedly")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_file_watcher_detects_change() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("watched.txt");
+        let other = dir.path().join("other.txt");
+        fs::write(&path, "one\n")?;
+
+        let watcher = FileWatcher::new(std::slice::from_ref(&path)).map_err(io::Error::other)?;
+
+        // 監視していないファイルの変更は無視する
+        fs::write(&other, "ignored\n")?;
+        let changed = watcher.wait(Some(Duration::from_millis(500))).map_err(io::Error::other)?;
+        assert!(!changed);
+
+        // 続けて書き込んでも1回の変更としてまとめる
+        fs::write(&path, "one two\n")?;
+        fs::write(&path, "one two three\n")?;
+        let changed = watcher.wait(Some(Duration::from_secs(5))).map_err(io::Error::other)?;
+        assert!(changed);
+        let changed = watcher.wait(Some(Duration::from_millis(500))).map_err(io::Error::other)?;
+        assert!(!changed);
+
+        Ok(())
+    }
+}